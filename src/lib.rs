@@ -1,455 +1,4297 @@
-#![feature(core, core_slice_ext, box_patterns, cell_extras, unboxed_closures, fnbox)]
-extern crate core;
 extern crate threadpool;
+#[cfg(feature = "futures")]
+extern crate futures;
 
 use std::mem;
+use std::convert::Infallible;
+use std::convert::TryInto;
+use std::fmt;
 use std::rc::Rc;
+use std::rc::Weak;
 use std::cell::RefCell;
+use std::cell::Cell;
 use std::cell::Ref;
-use std::boxed::FnBox;
-use core::slice::SliceExt;
+use std::cell::RefMut;
 use std::thread;
+use std::time::{Duration, Instant};
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::VecDeque;
 use std::sync::mpsc;
 use std::sync::mpsc::*;
 use threadpool::ThreadPool;
 
-pub struct Promise<T> {
-    state: Rc<RefCell<PromiseState<T>>>
+pub struct Promise<T, E = ()> {
+    state: Rc<RefCell<PromiseState<T, E>>>,
+    // Set by the first pending `poll` on this handle, which registers a single callback that
+    // wakes whatever waker the latest poll left here.
+    waker: Option<WakerSlot>
+}
+
+type WakerSlot = Rc<RefCell<Option<Waker>>>;
+
+impl<T, E> Promise<T, E> {
+    fn from_state(state: Rc<RefCell<PromiseState<T, E>>>) -> Promise<T, E> {
+        Promise { state, waker: None }
+    }
 }
 
 impl<T: 'static> Promise<T> {
     pub fn new() -> Promise<T> {
-        Promise {
-            state: Rc::new(RefCell::new(PromiseState::Unresolved))
-        }
+        Promise::with_state(PromiseState::Unresolved)
     }
     pub fn resolved(value: T) -> Promise<T> {
-        Promise {
-            state: Rc::new(RefCell::new(PromiseState::Resolved(value)))
+        Promise::with_state(PromiseState::Resolved(value))
+    }
+    pub fn lazy<F: FnOnce() -> T + 'static>(thunk: F) -> Promise<T> {
+        Promise::with_state(PromiseState::Lazy(Box::new(thunk)))
+    }
+    /// Settles the promise with the first value sent on `receiver`, once the returned source is
+    /// tracked by an `AsyncRunner`.
+    pub fn from_receiver(receiver: Receiver<T>) -> (Promise<T>, impl Resolveable) {
+        let promise = Promise::new();
+        let running = Running { receiver, promise_state: promise.state.clone(), into_result: Ok };
+        (promise, running)
+    }
+    pub fn deferred() -> (Resolver<T>, Promise<T>) {
+        let promise = Promise::new();
+        (Resolver { state: promise.state.clone() }, promise)
+    }
+    pub fn zip<T2: 'static>(&mut self, other: &mut Promise<T2>) -> Promise<(T, T2)> {
+        (self, other).join()
+    }
+}
+
+impl<T: 'static, E: 'static> Promise<T, E> {
+    fn with_state(state: PromiseState<T, E>) -> Promise<T, E> {
+        Promise::from_state(Rc::new(RefCell::new(state)))
+    }
+    /// Like `new`, but for any error type, which `new` fixes to `()`.
+    pub fn pending() -> Promise<T, E> {
+        Promise::with_state(PromiseState::Unresolved)
+    }
+    pub fn rejected(err: E) -> Promise<T, E> {
+        Promise::with_state(PromiseState::Rejected(err))
+    }
+    pub fn from_result(result: Result<T, E>) -> Promise<T, E> {
+        match result {
+            Ok(value) => Promise::with_state(PromiseState::Resolved(value)),
+            Err(err) => Promise::rejected(err)
         }
     }
     pub fn resolve(&mut self, value: T) {
-        self.state.resolve(value);
+        if self.try_resolve(value).is_err() {
+            panic!("Trying to resolve a promise that has already been settled.");
+        }
+    }
+    pub fn try_resolve(&mut self, value: T) -> Result<(), T> {
+        self.state.resolve(value)
+    }
+    pub fn reject(&mut self, err: E) {
+        if self.try_reject(err).is_err() {
+            panic!("Trying to reject a promise that has already been settled.");
+        }
+    }
+    pub fn try_reject(&mut self, err: E) -> Result<(), E> {
+        self.state.reject(err)
     }
-    pub fn value(&self) -> Option<Ref<T>> {
+    pub fn value(&self) -> Option<Ref<'_, T>> {
+        self.force();
         Ref::filter_map(self.state.borrow(), |state| match state {
-            &PromiseState::Resolved(ref value) => Some(value),
+            PromiseState::Resolved(value) => Some(value),
             _ => None
-        })
+        }).ok()
+    }
+    pub fn map_ref_view<U: ?Sized, F: FnOnce(&T) -> &U>(&self, f: F) -> Option<Ref<'_, U>> {
+        self.value().map(|value| Ref::map(value, f))
+    }
+    /// A readiness check for hand-written event loops; rejected promises stay `Pending`.
+    pub fn poll(&mut self) -> Poll<Ref<'_, T>> {
+        match self.value() {
+            Some(value) => Poll::Ready(value),
+            None => Poll::Pending
+        }
+    }
+    pub fn peek<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.value().map(|value| f(&value))
+    }
+    pub fn cloned(&self) -> Option<T> where T: Clone {
+        self.peek(T::clone)
+    }
+    pub fn error(&self) -> Option<Ref<'_, E>> {
+        Ref::filter_map(self.state.borrow(), |state| match state {
+            PromiseState::Rejected(err) => Some(err),
+            _ => None
+        }).ok()
+    }
+    pub fn is_resolved(&self) -> bool {
+        self.state.borrow().is_resolved()
+    }
+    pub fn is_rejected(&self) -> bool {
+        self.state.borrow().is_rejected()
+    }
+    pub fn is_pending(&self) -> bool {
+        !self.state.borrow().is_settled() && !self.state.borrow().is_moved()
+    }
+    pub fn callback_count(&self) -> usize {
+        self.state.borrow().callback_count()
+    }
+    pub fn downgrade(&self) -> WeakPromise<T, E> {
+        WeakPromise {
+            state: Rc::downgrade(&self.state)
+        }
     }
     pub fn into_value(self) -> T {
-        let mut s = self.state.borrow_mut();
+        self.force();
+        let mut s = self.state_mut();
         let state = mem::replace(&mut *s, PromiseState::Moved);
         match state {
             PromiseState::Resolved(value) => value,
             _ => panic!("Trying to call into_value on non-value promise.")
         }
     }
-    pub fn then_move<T2: 'static, F: FnOnce(T) -> T2 + 'static>(&mut self, transform: F) -> Promise<T2> {
-        let p = Promise::<T2>::new();
+    pub fn expect(self, msg: &str) -> T {
+        match self.into_value_opt() {
+            Some(value) => value,
+            None => panic!("{}", msg)
+        }
+    }
+    pub fn into_value_opt(self) -> Option<T> {
+        self.try_into_value().ok()
+    }
+    pub fn into_value_or(self, default: T) -> T {
+        self.into_value_opt().unwrap_or(default)
+    }
+    pub fn into_value_or_else<F: FnOnce() -> T>(self, f: F) -> T {
+        self.into_value_opt().unwrap_or_else(f)
+    }
+    pub fn try_into_value(self) -> Result<T, Promise<T, E>> {
+        self.force();
+        if !self.state.borrow().is_resolved() {
+            return Err(self);
+        }
+        Ok(self.into_value())
+    }
+    pub fn then_move<T2: 'static, F: FnOnce(T) -> T2 + 'static>(&mut self, transform: F) -> Promise<T2, E> {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then_move(move |result| {
+            let _ = p_state.settle(result.map(transform));
+        });
+        p
+    }
+    /// The task is only handed to the runner once the value is there, on its next
+    /// `try_resolve_all`, so it never holds a worker while waiting. A rejected upstream rejects
+    /// the returned promise without spawning anything.
+    pub fn then_spawn<T2: Send + 'static, X: Executor, F: FnOnce(T) -> T2 + Send + 'static>(&mut self, runner: &mut AsyncRunner<X>, transform: F) -> Promise<T2> where T: Send {
+        if runner.is_shut_down() {
+            return Promise::rejected(());
+        }
+        let (tx, rx) = mpsc::channel();
+        let (promise, running) = Running::new(rx);
+        let deferred = runner.deferred.clone();
+        self._then_move(move |result| {
+            match result {
+                Ok(value) => {
+                    let job: Job = Box::new(move || {
+                        let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(move || transform(value))).map_err(|_| ()));
+                    });
+                    deferred.borrow_mut().push_back((job, Box::new(running)));
+                },
+                Err(_) => {
+                    let _ = tx.send(Err(()));
+                    running.try_resolve();
+                }
+            }
+        });
+        runner.launch_queued();
+        promise
+    }
+    pub fn with_context<C: Clone + 'static>(mut self, ctx: C) -> Promise<(C, T), E> {
+        self.then_move(move |value| (ctx, value))
+    }
+    pub fn timed(mut self) -> Promise<(T, Duration), E> {
+        let start = Instant::now();
+        self.then_move(move |value| (value, start.elapsed()))
+    }
+    pub fn infallible(mut self) -> Promise<Result<T, Infallible>, E> {
+        self.then_move(Ok)
+    }
+    /// Also reports whether the transform already ran, in which case the returned promise is settled.
+    pub fn then_move_eager<T2: 'static, F: FnOnce(T) -> T2 + 'static>(&mut self, transform: F) -> (Promise<T2, E>, bool) {
+        self.force();
+        let eager = self.state.borrow().is_settled();
+        (self.then_move(transform), eager)
+    }
+    pub fn then_mut<F: FnOnce(&mut T) + 'static>(&mut self, mutate: F) -> Promise<T, E> {
+        self.then_move(move |mut value| {
+            mutate(&mut value);
+            value
+        })
+    }
+    pub fn then<T2: 'static, F: FnOnce(&T) -> T2 + 'static>(&mut self, transform: F) -> Promise<T2, E> where E: Clone {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then(move |result| {
+            let _ = p_state.settle(result.map(transform).map_err(|err| err.clone()));
+        });
+        p
+    }
+    /// Unlike `then_move`, any number of consumers can each take an owned clone of the value.
+    pub fn then_move_cloned<T2: 'static, F: FnOnce(T) -> T2 + 'static>(&mut self, transform: F) -> Promise<T2, E> where T: Clone, E: Clone {
+        self.then(move |value| transform(value.clone()))
+    }
+    /// If `token` is cancelled before this promise settles, `transform` never runs and the
+    /// returned promise is rejected with `Cancelled` instead.
+    pub fn then_cancellable<T2: 'static, F: FnOnce(&T) -> T2 + 'static>(&mut self, token: &CancelToken, transform: F) -> Promise<T2, E> where E: Clone + From<Cancelled> {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        let token = token.clone();
+        self._then(move |result| {
+            if token.is_cancelled() {
+                let _ = p_state.reject(E::from(Cancelled));
+            } else {
+                let _ = p_state.settle(result.map(transform).map_err(|err| err.clone()));
+            }
+        });
+        p
+    }
+    /// Like `then_cancellable`, but hands `transform` the value itself.
+    pub fn then_move_cancellable<T2: 'static, F: FnOnce(T) -> T2 + 'static>(&mut self, token: &CancelToken, transform: F) -> Promise<T2, E> where E: From<Cancelled> {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        let token = token.clone();
+        self._then_move(move |result| {
+            if token.is_cancelled() {
+                let _ = p_state.reject(E::from(Cancelled));
+            } else {
+                let _ = p_state.settle(result.map(transform));
+            }
+        });
+        p
+    }
+    pub fn then_move_promise<T2: 'static, F: FnOnce(T) -> Promise<T2, E> + 'static>(&mut self, transform: F) -> Promise<T2, E> {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then_move(move |result| {
+            match result {
+                Ok(value) => {
+                    let mut p2 = transform(value);
+                    p2._then_move(move |r2| {
+                        let _ = p_state.settle(r2);
+                    });
+                },
+                Err(err) => {
+                    let _ = p_state.reject(err);
+                }
+            }
+        });
+        p
+    }
+    pub fn then_promise<T2: 'static, F: FnOnce(&T) -> Promise<T2, E> + 'static>(&mut self, transform: F) -> Promise<T2, E> where E: Clone {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
         let p_state = p.state.clone();
-        self._then_move(move |value| {
-            p_state.resolve(transform(value));
+        self._then(move |result| {
+            match result {
+                Ok(value) => {
+                    let mut p2 = transform(value);
+                    p2._then_move(move |r2| {
+                        let _ = p_state.settle(r2);
+                    });
+                },
+                Err(err) => {
+                    let _ = p_state.reject(err.clone());
+                }
+            }
         });
         p
     }
-    pub fn then<T2: 'static, F: FnOnce(&T) -> T2 + 'static>(&mut self, transform: F) -> Promise<T2> {
-        let p = Promise::<T2>::new();
+    /// Alias for `then_move`.
+    ///
+    /// ```
+    /// use ppromise::Promise;
+    /// let mut p = Promise::resolved(5);
+    /// let p2 = p.map(|x| x.to_string());
+    /// assert_eq!(*p2.value().unwrap(), "5");
+    /// ```
+    pub fn map<T2: 'static, F: FnOnce(T) -> T2 + 'static>(&mut self, transform: F) -> Promise<T2, E> {
+        self.then_move(transform)
+    }
+    /// Alias for `then`.
+    ///
+    /// ```
+    /// use ppromise::Promise;
+    /// let mut p = Promise::resolved(5);
+    /// let p2 = p.map_ref(|x| x * 2);
+    /// assert_eq!(*p.value().unwrap(), 5);
+    /// assert_eq!(*p2.value().unwrap(), 10);
+    /// ```
+    pub fn map_ref<T2: 'static, F: FnOnce(&T) -> T2 + 'static>(&mut self, transform: F) -> Promise<T2, E> where E: Clone {
+        self.then(transform)
+    }
+    /// Alias for `then_move_promise`.
+    ///
+    /// ```
+    /// use ppromise::Promise;
+    /// let mut p = Promise::resolved(5);
+    /// let p2 = p.and_then(|x| Promise::resolved(x * 2));
+    /// assert_eq!(*p2.value().unwrap(), 10);
+    /// ```
+    pub fn and_then<T2: 'static, F: FnOnce(T) -> Promise<T2, E> + 'static>(&mut self, transform: F) -> Promise<T2, E> {
+        self.then_move_promise(transform)
+    }
+    pub fn then_move_result<T2: 'static, F: FnOnce(T) -> Result<T2, E> + 'static>(&mut self, transform: F) -> Promise<T2, E> {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
         let p_state = p.state.clone();
-        self._then(move |value| {
-            p_state.resolve(transform(value));
+        self._then_move(move |result| {
+            let _ = p_state.settle(result.and_then(transform));
         });
         p
     }
-    pub fn then_move_promise<T2: 'static, F: FnOnce(T) -> Promise<T2> + 'static>(&mut self, transform: F) -> Promise<T2> {
-        let p = Promise::<T2>::new();
+    pub fn then_result<T2: 'static, F: FnOnce(&T) -> Result<T2, E> + 'static>(&mut self, transform: F) -> Promise<T2, E> where E: Clone {
+        let p = Promise::<T2, E>::with_state(PromiseState::Unresolved);
         let p_state = p.state.clone();
-        self._then_move(move |value| {
-            let mut p2 = transform(value);
-            p2._then_move(move |v2| {
-                p_state.resolve(v2);
+        self._then(move |result| {
+            let _ = p_state.settle(match result {
+                Ok(value) => transform(value),
+                Err(err) => Err(err.clone())
             });
         });
         p
     }
-    pub fn then_promise<T2: 'static, F: FnOnce(&T) -> Promise<T2> + 'static>(&mut self, transform: F) -> Promise<T2> {
-        let p = Promise::<T2>::new();
+    /// Forwards the value by moving it into the returned promise, so no `T: Clone` is
+    /// needed, but like `then_move` this takes the value out of `self`.
+    pub fn inspect<F: FnOnce(&T) + 'static>(&mut self, f: F) -> Promise<T, E> {
+        let p = Promise::<T, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then_move(move |result| {
+            if let Ok(ref value) = result {
+                f(value);
+            }
+            let _ = p_state.settle(result);
+        });
+        p
+    }
+    pub fn on_resolve<F: FnOnce(&T) + 'static>(&mut self, f: F) {
+        self._then(move |result| {
+            if let Ok(value) = result {
+                f(value);
+            }
+        });
+    }
+    pub fn catch<F: FnOnce(E) -> T + 'static>(&mut self, handler: F) -> Promise<T> {
+        let p = Promise::<T>::new();
         let p_state = p.state.clone();
-        self._then(move |value| {
-            let mut p2 = transform(value);
-            p2._then_move(move |v2| {
-                p_state.resolve(v2);
+        self._then_move(move |result| {
+            let _ = p_state.resolve(match result {
+                Ok(value) => value,
+                Err(err) => handler(err)
             });
         });
         p
     }
-    fn _then_move<F: FnOnce(T) -> () + 'static>(&mut self, transform: F) {
+    pub fn recover_default(&mut self) -> Promise<T> where T: Default {
+        self.catch(|_| T::default())
+    }
+    pub fn or_else<F: FnOnce(E) -> Promise<T, E> + 'static>(&mut self, recover: F) -> Promise<T, E> {
+        let p = Promise::<T, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then_move(move |result| {
+            match result {
+                Ok(value) => {
+                    let _ = p_state.resolve(value);
+                },
+                Err(err) => {
+                    let mut p2 = recover(err);
+                    p2._then_move(move |r2| {
+                        let _ = p_state.settle(r2);
+                    });
+                }
+            }
+        });
+        p
+    }
+    pub fn map_err<E2: 'static, F: FnOnce(E) -> E2 + 'static>(&mut self, transform: F) -> Promise<T, E2> {
+        let p = Promise::<T, E2>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then_move(move |result| {
+            let _ = p_state.settle(result.map_err(transform));
+        });
+        p
+    }
+    /// The value is forwarded once the side effect settles, whether it resolved or not.
+    pub fn then_after<F: FnOnce(&T) -> Promise<()> + 'static>(&mut self, side_effect: F) -> Promise<T, E> {
+        let p = Promise::<T, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then_move(move |result| {
+            match result {
+                Ok(value) => {
+                    let mut after = side_effect(&value);
+                    after._then_move(move |_| {
+                        let _ = p_state.resolve(value);
+                    });
+                },
+                Err(err) => {
+                    let _ = p_state.reject(err);
+                }
+            }
+        });
+        p
+    }
+    pub fn finally<F: FnOnce() + 'static>(&mut self, f: F) -> Promise<T, E> {
+        let p = Promise::<T, E>::with_state(PromiseState::Unresolved);
+        let p_state = p.state.clone();
+        self._then_move(move |result| {
+            f();
+            let _ = p_state.settle(result);
+        });
+        p
+    }
+    fn force(&self) {
+        if !self.state.borrow().is_lazy() {
+            return;
+        }
+        let state = mem::replace(&mut *self.state.borrow_mut(), PromiseState::Unresolved);
+        if let PromiseState::Lazy(thunk) = state {
+            let _ = self.state.resolve(thunk());
+        }
+    }
+    fn _then_move<F: FnOnce(Result<T, E>) + 'static>(&mut self, transform: F) {
+        self.force();
         if self.state.borrow().is_moved() {
             panic!("Trying to move promise value that has already been moved.");
         }
-        if self.state.borrow().is_resolved() {
-            let mut s = self.state.borrow_mut();
-            if let PromiseState::Resolved(value) = mem::replace(&mut *s, PromiseState::Moved) {
-                return transform(value);
-            } else {
-                unreachable!();
+        if self.state.borrow().is_settled() {
+            let state = mem::replace(&mut *self.state_mut(), PromiseState::Moved);
+            match state {
+                PromiseState::Resolved(value) => return transform(Ok(value)),
+                PromiseState::Rejected(err) => return transform(Err(err)),
+                _ => unreachable!()
             }
         }
-        let mut s = self.state.borrow_mut();
+        let mut s = self.state_mut();
         let state = mem::replace(&mut *s, PromiseState::Unresolved);
-        *s = state.insert_then_move(move |value: T| {
-            transform(value);
+        *s = state.insert_then_move(move |result: Result<T, E>| {
+            transform(result);
         });
     }
-    fn _then<F: FnOnce(&T) -> () + 'static>(&mut self, transform: F) {
+    fn _then<F: FnOnce(Result<&T, &E>) + 'static>(&mut self, transform: F) {
+        self.force();
         if self.state.borrow().is_moved() {
             panic!("Trying to borrow promise value that has already been moved.");
         }
-        if let &PromiseState::Resolved(ref value) = &*self.state.borrow() {
-            return transform(value);
+        match &*self.state.borrow() {
+            PromiseState::Resolved(value) => return transform(Ok(value)),
+            PromiseState::Rejected(err) => return transform(Err(err)),
+            _ => {}
         }
-        let mut s = self.state.borrow_mut();
+        let mut s = self.state_mut();
         let state = mem::replace(&mut *s, PromiseState::Unresolved);
-        *s = state.insert_then(move |value: &T| {
-            transform(value);
+        *s = state.insert_then(move |result: Result<&T, &E>| {
+            transform(result);
         });
     }
+    // A settled promise's state stays borrowed while its `then` callbacks run, so a callback that
+    // tries to take the value out of a clone gets this panic instead of a bare `BorrowMutError`.
+    fn state_mut(&self) -> RefMut<'_, PromiseState<T, E>> {
+        self.state.try_borrow_mut()
+            .expect("Trying to move promise value while its callbacks are borrowing it.")
+    }
 }
 
-pub fn join<T1: 'static, T2: 'static>(p1: &mut Promise<T1>, p2: &mut Promise<T2>) -> Promise<(T1, T2)> {
-    (p1, p2).join()
-}
-pub fn join3<T1: 'static, T2: 'static, T3: 'static>(p1: &mut Promise<T1>, p2: &mut Promise<T2>, p3: &mut Promise<T3>) -> Promise<(T1, T2, T3)> {
-    (p1, p2, p3).join()
-}
-
-pub trait Joinable<T> {
-    fn join(self) -> Promise<T>;
+pub struct WeakPromise<T, E = ()> {
+    state: Weak<RefCell<PromiseState<T, E>>>
 }
 
-impl<'a, T: 'static> Joinable<Vec<T>> for Vec<Promise<T>> {
-    fn join(mut self) -> Promise<Vec<T>> {
-        self.iter_mut().collect::<Vec<&mut Promise<T>>>().join()
+impl<T, E> WeakPromise<T, E> {
+    pub fn upgrade(&self) -> Option<Promise<T, E>> {
+        self.state.upgrade().map(Promise::from_state)
     }
 }
 
-impl<'a, T: 'static> Joinable<Vec<T>> for Vec<&'a mut Promise<T>> {
-    fn join(mut self) -> Promise<Vec<T>> {
-        let mut p: Promise<Vec<T>> = self[0].then_move(|x| vec![x]);
-        for i in 1..self.len() {
-            let mut p2 = &mut self[i];
-            p = p2.then_move_promise(move |x| {
-                p.then_move(move |mut xs: Vec<T>| { xs.push(x); xs })
-            });
+impl<T, E> Clone for WeakPromise<T, E> {
+    fn clone(&self) -> WeakPromise<T, E> {
+        WeakPromise {
+            state: self.state.clone()
         }
-        p
     }
 }
 
-impl<'a, T1: 'static, T2: 'static> Joinable<(T1, T2)> for (&'a mut Promise<T1>, &'a mut Promise<T2>) {
-    fn join(mut self) -> Promise<(T1, T2)> {
-        let mut p1 = Promise { state: self.1.state.clone() };
-        self.0.then_move_promise(move |x1| {
-            p1.then_move(move |x2| {
-                (x1, x2)
-            })
-        })
-    }
+/// The producing half of `Promise::deferred`, which can only resolve its promise.
+pub struct Resolver<T> {
+    state: Rc<RefCell<PromiseState<T, ()>>>
 }
 
-impl<'a, T1: 'static, T2: 'static, T3: 'static> Joinable<(T1, T2, T3)> for (&'a mut Promise<T1>, &'a mut Promise<T2>, &'a mut Promise<T3>) {
-    fn join(mut self) -> Promise<(T1, T2, T3)> {
-        let mut p1 = Promise { state: self.1.state.clone() };
-        let mut p2 = Promise { state: self.2.state.clone() };
-        self.0.then_move_promise(move |x1| {
-            p1.then_move_promise(move |x2| {
-                p2.then_move(move |x3| {
-                    (x1, x2, x3)
-                })
-            })
-        })
+impl<T: 'static> Resolver<T> {
+    pub fn resolve(self, value: T) {
+        Promise::from_state(self.state).resolve(value);
     }
 }
 
+/// Rejects the promise returned by `then_cancellable` or `then_move_cancellable` once its
+/// `CancelToken` has been cancelled. The promise's error type has to be convertible from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
 
-trait Resolveable {
-    fn try_resolve(&self) -> bool;
+impl From<Cancelled> for () {
+    fn from(_: Cancelled) {}
 }
 
-struct Running<T> {
-    receiver: Receiver<T>,
-    promise_state: Rc<RefCell<PromiseState<T>>>
-}
-
-impl<T: 'static> Resolveable for Running<T> {
-    fn try_resolve(&self) -> bool {
-        match self.receiver.try_recv() {
-            Ok(value) => {
-                self.promise_state.resolve(value);
-                true
-            },
-            _ => false
-        }
+impl From<Cancelled> for String {
+    fn from(_: Cancelled) -> String {
+        "the callback has been cancelled".to_string()
     }
 }
 
-pub struct AsyncRunner {
-    running: Vec<Box<Resolveable>>,
-    pool: Option<ThreadPool>
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    cancelled: Rc<Cell<bool>>
 }
-impl AsyncRunner {
-    pub fn new() -> AsyncRunner {
-        AsyncRunner {
-            running: vec![],
-            pool: None
-        }
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
     }
-    pub fn new_pooled(threads: usize) -> AsyncRunner {
-        AsyncRunner {
-            running: vec![],
-            pool: Some(ThreadPool::new(threads))
-        }
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
     }
-    pub fn exec_async<T: Send + Sized + 'static, F: Fn() -> T + Send + Sized + 'static>(&mut self, run: F) -> Promise<T> {
-        let (tx, rx) = mpsc::channel();
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+}
 
-        let f = move || {
-            match tx.send(run()) {
-                Ok(()) => {},
-                Err(err) => panic!("Thread error: {}", err)
-            }
-        };
+/// A sequence of promises that yields their values in the order they resolve.
+///
+/// The stream doesn't drive anything itself: values show up as the `AsyncRunner` that owns the
+/// underlying tasks resolves them, so call `poll_next` alongside `try_resolve_all`.
+pub struct PromiseStream<T> {
+    completed: Rc<RefCell<VecDeque<T>>>,
+    pending: Rc<Cell<usize>>
+}
 
-        if let &Some(ref pool) = &self.pool {
-            pool.execute(f);
-        } else {
-            thread::spawn(f);
+impl<T: 'static> PromiseStream<T> {
+    pub fn new() -> PromiseStream<T> {
+        PromiseStream { completed: Rc::new(RefCell::new(VecDeque::new())), pending: Rc::new(Cell::new(0)) }
+    }
+    /// Adds a promise whose value is yielded once it resolves. A rejected promise yields nothing:
+    /// it is dropped from the stream, which ends once every other promise has settled.
+    pub fn push(&mut self, mut promise: Promise<T>) {
+        let completed = self.completed.clone();
+        let pending = self.pending.clone();
+        pending.set(pending.get() + 1);
+        promise._then_move(move |result| {
+            pending.set(pending.get() - 1);
+            if let Ok(value) = result {
+                completed.borrow_mut().push_back(value);
+            }
+        });
+    }
+    pub fn poll_next(&mut self) -> Poll<Option<T>> {
+        match self.completed.borrow_mut().pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if self.pending.get() == 0 => Poll::Ready(None),
+            None => Poll::Pending
         }
+    }
+    pub fn len(&self) -> usize {
+        self.completed.borrow().len() + self.pending.get()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
-        let promise = Promise::new();
-        self.running.push(Box::new(Running { receiver: rx, promise_state: promise.state.clone() }));
-        promise
+impl<T: 'static> Default for PromiseStream<T> {
+    fn default() -> PromiseStream<T> {
+        PromiseStream::new()
     }
-    pub fn try_resolve_all(&mut self) {
-        let running = mem::replace(&mut self.running, Vec::new());
-        self.running = running.into_iter().filter(|r| !r.try_resolve()).collect();
+}
+
+impl<T: 'static, E: 'static> Promise<Promise<T, E>, E> {
+    pub fn flatten(mut self) -> Promise<T, E> {
+        self.then_move_promise(|inner| inner)
     }
 }
 
+impl<T: 'static> Default for Promise<T> {
+    fn default() -> Promise<T> {
+        Promise::new()
+    }
+}
 
-enum PromiseState<T> {
-    Unresolved,
-    Moved,
-    Resolved(T),
-    Then(Vec<Box<FnBox(&T) -> ()>>, Box<PromiseState<T>>),
-    ThenMove(Box<FnBox(T) -> ()>)
+impl<T: 'static> From<T> for Promise<T> {
+    fn from(value: T) -> Promise<T> {
+        Promise::resolved(value)
+    }
 }
 
-impl<T> PromiseState<T> {
-    fn is_resolved(&self) -> bool {
-        if let &PromiseState::Resolved(_) = self {
-            true
-        } else {
-            false
-        }
+/// Cloning a promise creates another handle to the same underlying state, not a copy of the
+/// value: resolving either handle resolves both. Consuming calls such as `into_value` and
+/// `then_move` take the value out for every handle.
+impl<T, E> Clone for Promise<T, E> {
+    fn clone(&self) -> Promise<T, E> {
+        Promise::from_state(self.state.clone())
     }
-    fn is_moved(&self) -> bool {
-        if let &PromiseState::Moved = self {
-            true
-        } else {
-            false
+}
+
+impl<T: fmt::Debug, E: fmt::Debug> fmt::Debug for Promise<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.state.try_borrow() {
+            Ok(state) => f.debug_tuple("Promise").field(&*state).finish(),
+            Err(_) => f.write_str("Promise(<borrowed>)")
         }
     }
-    fn insert_then<F: FnOnce(&T) -> () + 'static>(self, transform: F) -> PromiseState<T> {
-        match self {
-            PromiseState::Unresolved => PromiseState::Then(vec![Box::new(transform)], Box::new(PromiseState::Unresolved)),
-            PromiseState::Then(mut ts, then) => {
-                ts.push(Box::new(transform));
-                PromiseState::Then(ts, then)
-            },
-            PromiseState::ThenMove(t) => {
-                PromiseState::Then(vec![Box::new(transform)], Box::new(PromiseState::ThenMove(t)))
-            },
-            _ => unreachable!()
+}
+
+/// Two promises are equal only when both are resolved to equal values.
+///
+/// Pending, rejected and moved promises are never equal to anything, not even to themselves, so
+/// this is a partial equivalence only. Comparing forces lazy promises.
+impl<T: PartialEq + 'static, E: 'static> PartialEq for Promise<T, E> {
+    fn eq(&self, other: &Promise<T, E>) -> bool {
+        match (self.value(), other.value()) {
+            (Some(a), Some(b)) => *a == *b,
+            _ => false
         }
     }
-    fn insert_then_move<F: FnOnce(T) -> () + 'static>(self, transform: F) -> PromiseState<T> {
-        match self {
-            PromiseState::Unresolved => PromiseState::ThenMove(Box::new(transform)),
-            PromiseState::Then(ts, box then) => {
-                PromiseState::Then(ts, Box::new(then.insert_then_move(transform)))
-            },
-            PromiseState::ThenMove(_) => {
-                panic!("Cannot move value out of promise twice.");
-            },
-            _ => unreachable!()
+}
+
+/// Resolves to `Ok` or `Err` once the promise settles. `Promise` is `Rc`-based and not
+/// `Send`, so it can only be awaited on a single-threaded executor.
+impl<T: 'static, E: 'static> Future for Promise<T, E> {
+    type Output = Result<T, E>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, E>> {
+        let promise = self.get_mut();
+        promise.force();
+        if promise.state.borrow().is_settled() {
+            let mut s = promise.state_mut();
+            return Poll::Ready(match mem::replace(&mut *s, PromiseState::Moved) {
+                PromiseState::Resolved(value) => Ok(value),
+                PromiseState::Rejected(err) => Err(err),
+                _ => unreachable!()
+            });
         }
-    }
-    fn transform(self, value: T) -> PromiseState<T> {
-        match self {
-            PromiseState::Unresolved => PromiseState::Resolved(value),
-            PromiseState::Then(transforms, box then) => {
-                for transform in transforms {
-                    transform.call_box((&value,));
-                }
-                then.transform(value)
-            },
-            PromiseState::ThenMove(transform) => {
-                transform(value);
-                PromiseState::Unresolved
-            },
-            _ => unreachable!()
+        match &promise.waker {
+            Some(slot) => *slot.borrow_mut() = Some(cx.waker().clone()),
+            None => {
+                let slot = Rc::new(RefCell::new(Some(cx.waker().clone())));
+                let registered = slot.clone();
+                promise._then(move |_| {
+                    if let Some(waker) = registered.borrow_mut().take() {
+                        waker.wake();
+                    }
+                });
+                promise.waker = Some(slot);
+            }
         }
+        Poll::Pending
     }
 }
 
-trait ResolvableState<T> {
-    fn resolve(&self, value: T);
+/// Adapts a `Promise` for code built on the `futures` crate.
+///
+/// Like the promise itself it is not `Send`, so it can only be driven by a single-threaded
+/// executor such as `futures::executor::LocalPool`.
+#[cfg(feature = "futures")]
+pub struct PromiseFuture<T, E = ()> {
+    promise: Promise<T, E>,
+    terminated: bool
 }
-impl<T> ResolvableState<T> for Rc<RefCell<PromiseState<T>>> {
-    fn resolve(&self, value: T) {
-        let mut s = self.borrow_mut();
-        let state = mem::replace(&mut *s, PromiseState::Unresolved);
-        *s = state.transform(value);
+
+#[cfg(feature = "futures")]
+impl<T: 'static, E: 'static> Promise<T, E> {
+    pub fn into_futures(self) -> PromiseFuture<T, E> {
+        PromiseFuture { promise: self, terminated: false }
     }
 }
 
-#[test]
-fn test_promise_resolve() {
-    let mut p = Promise::new();
-    p.resolve(5);
-    assert_eq!(*p.value().unwrap(), 5);
+#[cfg(feature = "futures")]
+impl<T: 'static, E: 'static> futures::Future for PromiseFuture<T, E> {
+    type Output = Result<T, E>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, E>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.promise).poll(cx);
+        this.terminated = poll.is_ready();
+        poll
+    }
 }
 
-#[test]
-fn test_promise_resolved_then_promise() {
-    let mut p = Promise::resolved(5);
-    let p2 = p.then_promise(|val| Promise::resolved(val * 2));
-    assert_eq!(*p2.value().unwrap(), 10);
+#[cfg(feature = "futures")]
+impl<T: 'static, E: 'static> futures::future::FusedFuture for PromiseFuture<T, E> {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
 }
 
-#[test]
-fn test_promise_then_promise() {
-    let mut p = Promise::new();
-    let p2 = p.then_promise(|val| Promise::resolved(val * 2));
-    p.resolve(5);
-    assert_eq!(*p2.value().unwrap(), 10);
+impl<C: 'static, T: 'static, E: 'static> Promise<(C, T), E> {
+    /// Hands the context to each stage by reference and carries it along to the next one.
+    pub fn then_ctx<T2: 'static, F: FnOnce(&C, T) -> T2 + 'static>(&mut self, transform: F) -> Promise<(C, T2), E> {
+        self.then_move(move |(ctx, value)| {
+            let value = transform(&ctx, value);
+            (ctx, value)
+        })
+    }
 }
 
-#[test]
-fn test_promise_resolved_then_move_promise() {
-    let mut p = Promise::resolved(5);
-    let p2 = p.then_move_promise(|val| Promise::resolved(val * 2));
-    assert_eq!(*p2.value().unwrap(), 10);
-}
+impl<T: 'static, E: 'static> Promise<Vec<T>, E> {
+    pub fn into_value_iter(self) -> impl Iterator<Item = T> {
+        self.into_value().into_iter()
+    }
+    pub fn map_each<T2: 'static, F: FnMut(T) -> T2 + 'static>(&mut self, transform: F) -> Promise<Vec<T2>, E> {
+        self.then_move(move |values| values.into_iter().map(transform).collect())
+    }
+}
+
+pub fn join<T1: 'static, T2: 'static>(p1: &mut Promise<T1>, p2: &mut Promise<T2>) -> Promise<(T1, T2)> {
+    (p1, p2).join()
+}
+pub fn join3<T1: 'static, T2: 'static, T3: 'static>(p1: &mut Promise<T1>, p2: &mut Promise<T2>, p3: &mut Promise<T3>) -> Promise<(T1, T2, T3)> {
+    (p1, p2, p3).join()
+}
+pub fn join4<T1: 'static, T2: 'static, T3: 'static, T4: 'static>(p1: &mut Promise<T1>, p2: &mut Promise<T2>, p3: &mut Promise<T3>, p4: &mut Promise<T4>) -> Promise<(T1, T2, T3, T4)> {
+    (p1, p2, p3, p4).join()
+}
+pub fn join5<T1: 'static, T2: 'static, T3: 'static, T4: 'static, T5: 'static>(p1: &mut Promise<T1>, p2: &mut Promise<T2>, p3: &mut Promise<T3>, p4: &mut Promise<T4>, p5: &mut Promise<T5>) -> Promise<(T1, T2, T3, T4, T5)> {
+    (p1, p2, p3, p4, p5).join()
+}
+pub fn join6<T1: 'static, T2: 'static, T3: 'static, T4: 'static, T5: 'static, T6: 'static>(p1: &mut Promise<T1>, p2: &mut Promise<T2>, p3: &mut Promise<T3>, p4: &mut Promise<T4>, p5: &mut Promise<T5>, p6: &mut Promise<T6>) -> Promise<(T1, T2, T3, T4, T5, T6)> {
+    (p1, p2, p3, p4, p5, p6).join()
+}
+
+pub fn race<T: 'static, E: 'static>(promises: Vec<&mut Promise<T, E>>) -> Promise<T, E> {
+    select(promises).then_move(|(_, value)| value)
+}
+
+pub fn select<T: 'static, E: 'static>(promises: Vec<&mut Promise<T, E>>) -> Promise<(usize, T), E> {
+    if promises.is_empty() {
+        panic!("Trying to race an empty list of promises.");
+    }
+    let p = Promise::<(usize, T), E>::with_state(PromiseState::Unresolved);
+    let settled = Rc::new(Cell::new(false));
+    for (i, promise) in promises.into_iter().enumerate() {
+        let p_state = p.state.clone();
+        let settled = settled.clone();
+        promise._then_move(move |result| {
+            if !settled.get() {
+                settled.set(true);
+                let _ = p_state.settle(result.map(|value| (i, value)));
+            }
+        });
+    }
+    p
+}
+
+pub fn join_all<T: 'static>(promises: Vec<Promise<T>>) -> Promise<Vec<T>> {
+    promises.join()
+}
+
+pub fn all_with_index<T: 'static>(promises: Vec<&mut Promise<T>>) -> Promise<Vec<(usize, T)>> {
+    promises.join().then_move(|values| values.into_iter().enumerate().collect())
+}
+
+pub fn race_all<T: 'static, E: 'static>(mut promises: Vec<Promise<T, E>>) -> Promise<T, E> {
+    race(promises.iter_mut().collect())
+}
+
+pub fn any<T: 'static, E: 'static>(promises: Vec<&mut Promise<T, E>>) -> Promise<T, Vec<E>> {
+    if promises.is_empty() {
+        return Promise::rejected(vec![]);
+    }
+    let p = Promise::<T, Vec<E>>::with_state(PromiseState::Unresolved);
+    let count = promises.len();
+    let settled = Rc::new(Cell::new(false));
+    let rejected = Rc::new(Cell::new(0));
+    let errors: Rc<RefCell<Vec<Option<E>>>> = Rc::new(RefCell::new((0..count).map(|_| None).collect()));
+    for (i, promise) in promises.into_iter().enumerate() {
+        let p_state = p.state.clone();
+        let settled = settled.clone();
+        let rejected = rejected.clone();
+        let errors = errors.clone();
+        promise._then_move(move |result| {
+            if settled.get() {
+                return;
+            }
+            match result {
+                Ok(value) => {
+                    settled.set(true);
+                    let _ = p_state.resolve(value);
+                },
+                Err(err) => {
+                    errors.borrow_mut()[i] = Some(err);
+                    rejected.set(rejected.get() + 1);
+                    if rejected.get() == count {
+                        settled.set(true);
+                        let errors = mem::take(&mut *errors.borrow_mut());
+                        let _ = p_state.reject(errors.into_iter().map(|err| err.unwrap()).collect());
+                    }
+                }
+            }
+        });
+    }
+    p
+}
+
+/// Resolves with the first `Some` in index order. A later `Some` only wins once every promise
+/// before it has resolved to `None`, but nothing after the winner is waited for. A rejection
+/// that comes before the winner in index order rejects the result.
+pub fn join_first_some<T: 'static, E: 'static>(promises: Vec<&mut Promise<Option<T>, E>>) -> Promise<Option<T>, E> {
+    let p = Promise::<Option<T>, E>::with_state(PromiseState::Unresolved);
+    let count = promises.len();
+    if count == 0 {
+        let _ = p.state.resolve(None);
+        return p;
+    }
+    let next = Rc::new(Cell::new(0));
+    let results = Rc::new(RefCell::new((0..count).map(|_| None).collect::<Vec<_>>()));
+    for (i, promise) in promises.into_iter().enumerate() {
+        let p_state = p.state.clone();
+        let next = next.clone();
+        let results = results.clone();
+        promise._then_move(move |result| {
+            if next.get() == count {
+                return;
+            }
+            results.borrow_mut()[i] = Some(result);
+            while next.get() < count {
+                let result = match results.borrow_mut()[next.get()].take() {
+                    Some(result) => result,
+                    None => return
+                };
+                match result {
+                    Ok(None) => next.set(next.get() + 1),
+                    Ok(Some(value)) => {
+                        next.set(count);
+                        let _ = p_state.resolve(Some(value));
+                        return;
+                    },
+                    Err(err) => {
+                        next.set(count);
+                        let _ = p_state.reject(err);
+                        return;
+                    }
+                }
+            }
+            let _ = p_state.resolve(None);
+        });
+    }
+    p
+}
+
+pub fn all_settled<T: 'static, E: 'static>(promises: Vec<&mut Promise<T, E>>) -> Promise<Vec<Result<T, E>>> {
+    if promises.is_empty() {
+        return Promise::resolved(vec![]);
+    }
+    let p = Promise::<Vec<Result<T, E>>>::new();
+    let remaining = Rc::new(Cell::new(promises.len()));
+    let results = Rc::new(RefCell::new((0..promises.len()).map(|_| None).collect::<Vec<Option<Result<T, E>>>>()));
+    for (i, promise) in promises.into_iter().enumerate() {
+        let p_state = p.state.clone();
+        let remaining = remaining.clone();
+        let results = results.clone();
+        promise._then_move(move |result| {
+            results.borrow_mut()[i] = Some(result);
+            remaining.set(remaining.get() - 1);
+            if remaining.get() == 0 {
+                let results = mem::take(&mut *results.borrow_mut());
+                let _ = p_state.resolve(results.into_iter().map(|result| result.unwrap()).collect());
+            }
+        });
+    }
+    p
+}
+
+struct Fold<T, A, F> {
+    acc: Option<A>,
+    next: usize,
+    values: Vec<Option<T>>,
+    f: F
+}
+
+/// Values are folded in as soon as every earlier index has arrived, so only out-of-order values are buffered.
+pub fn fold<T: 'static, A: 'static, F: FnMut(A, T) -> A + 'static>(promises: Vec<&mut Promise<T>>, init: A, f: F) -> Promise<A> {
+    if promises.is_empty() {
+        return Promise::resolved(init);
+    }
+    let p = Promise::<A>::new();
+    let fold = Rc::new(RefCell::new(Fold {
+        acc: Some(init),
+        next: 0,
+        values: (0..promises.len()).map(|_| None).collect(),
+        f
+    }));
+    for (i, promise) in promises.into_iter().enumerate() {
+        let p_state = p.state.clone();
+        let fold = fold.clone();
+        promise._then_move(move |result| {
+            let mut fold = fold.borrow_mut();
+            let fold = &mut *fold;
+            if fold.acc.is_none() {
+                return;
+            }
+            match result {
+                Ok(value) => {
+                    fold.values[i] = Some(value);
+                    while fold.next < fold.values.len() {
+                        match fold.values[fold.next].take() {
+                            Some(value) => {
+                                let acc = fold.acc.take().unwrap();
+                                fold.acc = Some((fold.f)(acc, value));
+                                fold.next += 1;
+                            },
+                            None => break
+                        }
+                    }
+                    if fold.next == fold.values.len() {
+                        let _ = p_state.resolve(fold.acc.take().unwrap());
+                    }
+                },
+                Err(err) => {
+                    fold.acc = None;
+                    let _ = p_state.reject(err);
+                }
+            }
+        });
+    }
+    p
+}
+
+pub trait Joinable<T> {
+    fn join(self) -> Promise<T>;
+}
+
+impl<T: 'static> Joinable<Vec<T>> for Vec<Promise<T>> {
+    fn join(mut self) -> Promise<Vec<T>> {
+        self.iter_mut().collect::<Vec<&mut Promise<T>>>().join()
+    }
+}
+
+impl<T: 'static> Joinable<Vec<T>> for Vec<&mut Promise<T>> {
+    fn join(self) -> Promise<Vec<T>> {
+        if self.is_empty() {
+            return Promise::resolved(vec![]);
+        }
+        let p = Promise::<Vec<T>>::new();
+        let remaining = Rc::new(Cell::new(self.len()));
+        let values: Rc<RefCell<Vec<Option<T>>>> = Rc::new(RefCell::new((0..self.len()).map(|_| None).collect()));
+        for (i, promise) in self.into_iter().enumerate() {
+            let p_state = p.state.clone();
+            let remaining = remaining.clone();
+            let values = values.clone();
+            promise._then_move(move |result| {
+                if remaining.get() == 0 {
+                    return;
+                }
+                match result {
+                    Ok(value) => {
+                        values.borrow_mut()[i] = Some(value);
+                        remaining.set(remaining.get() - 1);
+                        if remaining.get() == 0 {
+                            let values = mem::take(&mut *values.borrow_mut());
+                            let _ = p_state.resolve(values.into_iter().map(|value| value.unwrap()).collect());
+                        }
+                    },
+                    Err(err) => {
+                        remaining.set(0);
+                        let _ = p_state.reject(err);
+                    }
+                }
+            });
+        }
+        p
+    }
+}
+
+impl<T: 'static, const N: usize> Joinable<[T; N]> for [&mut Promise<T>; N] {
+    fn join(self) -> Promise<[T; N]> {
+        Vec::from(self).join().then_move(|values| {
+            values.try_into().unwrap_or_else(|_| unreachable!())
+        })
+    }
+}
+
+impl<'a, T1: 'static, T2: 'static> Joinable<(T1, T2)> for (&'a mut Promise<T1>, &'a mut Promise<T2>) {
+    fn join(self) -> Promise<(T1, T2)> {
+        let mut p1 = self.1.clone();
+        self.0.then_move_promise(move |x1| {
+            p1.then_move(move |x2| {
+                (x1, x2)
+            })
+        })
+    }
+}
+
+impl<'a, T1: 'static, T2: 'static, T3: 'static> Joinable<(T1, T2, T3)> for (&'a mut Promise<T1>, &'a mut Promise<T2>, &'a mut Promise<T3>) {
+    fn join(self) -> Promise<(T1, T2, T3)> {
+        let mut p1 = self.1.clone();
+        let mut p2 = self.2.clone();
+        self.0.then_move_promise(move |x1| {
+            p1.then_move_promise(move |x2| {
+                p2.then_move(move |x3| {
+                    (x1, x2, x3)
+                })
+            })
+        })
+    }
+}
+
+impl<'a, T1: 'static, T2: 'static, T3: 'static, T4: 'static> Joinable<(T1, T2, T3, T4)> for (&'a mut Promise<T1>, &'a mut Promise<T2>, &'a mut Promise<T3>, &'a mut Promise<T4>) {
+    fn join(self) -> Promise<(T1, T2, T3, T4)> {
+        let mut p1 = self.1.clone();
+        let mut p2 = self.2.clone();
+        let mut p3 = self.3.clone();
+        self.0.then_move_promise(move |x1| {
+            p1.then_move_promise(move |x2| {
+                p2.then_move_promise(move |x3| {
+                    p3.then_move(move |x4| {
+                        (x1, x2, x3, x4)
+                    })
+                })
+            })
+        })
+    }
+}
+
+impl<'a, T1: 'static, T2: 'static, T3: 'static, T4: 'static, T5: 'static> Joinable<(T1, T2, T3, T4, T5)> for (&'a mut Promise<T1>, &'a mut Promise<T2>, &'a mut Promise<T3>, &'a mut Promise<T4>, &'a mut Promise<T5>) {
+    fn join(self) -> Promise<(T1, T2, T3, T4, T5)> {
+        let mut p1 = self.1.clone();
+        let mut p2 = self.2.clone();
+        let mut p3 = self.3.clone();
+        let mut p4 = self.4.clone();
+        self.0.then_move_promise(move |x1| {
+            p1.then_move_promise(move |x2| {
+                p2.then_move_promise(move |x3| {
+                    p3.then_move_promise(move |x4| {
+                        p4.then_move(move |x5| {
+                            (x1, x2, x3, x4, x5)
+                        })
+                    })
+                })
+            })
+        })
+    }
+}
+
+impl<'a, T1: 'static, T2: 'static, T3: 'static, T4: 'static, T5: 'static, T6: 'static> Joinable<(T1, T2, T3, T4, T5, T6)> for (&'a mut Promise<T1>, &'a mut Promise<T2>, &'a mut Promise<T3>, &'a mut Promise<T4>, &'a mut Promise<T5>, &'a mut Promise<T6>) {
+    fn join(self) -> Promise<(T1, T2, T3, T4, T5, T6)> {
+        let mut p1 = self.1.clone();
+        let mut p2 = self.2.clone();
+        let mut p3 = self.3.clone();
+        let mut p4 = self.4.clone();
+        let mut p5 = self.5.clone();
+        self.0.then_move_promise(move |x1| {
+            p1.then_move_promise(move |x2| {
+                p2.then_move_promise(move |x3| {
+                    p3.then_move_promise(move |x4| {
+                        p4.then_move_promise(move |x5| {
+                            p5.then_move(move |x6| {
+                                (x1, x2, x3, x4, x5, x6)
+                            })
+                        })
+                    })
+                })
+            })
+        })
+    }
+}
+
+
+/// Joins any number of `&mut Promise<T>` arguments into a promise of the flat tuple of values.
+///
+/// Works like the tuple `Joinable` impls, without their arity ceiling.
+#[macro_export]
+macro_rules! join {
+    ($($p:expr),+ $(,)?) => {
+        $crate::join!(@bind [] $($p),+)
+    };
+    // Every expansion step introduces its own `h`/`x`; hygiene keeps them apart.
+    (@bind [$($h:ident)*] $p:expr $(, $rest:expr)*) => {{
+        let mut h = ($p).clone();
+        $crate::join!(@bind [$($h)* h] $($rest),*)
+    }};
+    (@bind [$($h:ident)+]) => {
+        $crate::join!(@chain [] $($h)+)
+    };
+    (@chain [$($x:ident)*] $h:ident) => {
+        $h.then_move(move |x| ($($x,)* x,))
+    };
+    (@chain [$($x:ident)*] $h:ident $($rest:ident)+) => {
+        $h.then_move_promise(move |x| $crate::join!(@chain [$($x)* x] $($rest)+))
+    };
+}
+
+/// A pollable source of resolution that an `AsyncRunner` can drive through `track`.
+///
+/// ```
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+/// use ppromise::{AsyncRunner, Promise, Resolution, Resolveable};
+///
+/// struct Flag {
+///     set: Rc<Cell<bool>>,
+///     promise: Promise<&'static str>
+/// }
+///
+/// impl Resolveable for Flag {
+///     fn try_resolve(&self) -> Resolution {
+///         if !self.set.get() {
+///             return Resolution::Pending;
+///         }
+///         self.promise.clone().resolve("set");
+///         Resolution::Settled
+///     }
+/// }
+///
+/// let set = Rc::new(Cell::new(false));
+/// let p = Promise::new();
+/// let mut runner = AsyncRunner::new();
+/// runner.track(Box::new(Flag { set: set.clone(), promise: p.clone() }));
+/// runner.try_resolve_all();
+/// assert!(p.value().is_none());
+/// set.set(true);
+/// runner.try_resolve_all();
+/// assert_eq!(*p.value().unwrap(), "set");
+/// ```
+pub trait Resolveable {
+    /// Settles the source's promise if its result is available, without blocking. Anything but
+    /// `Pending` means the source is done and the runner can stop tracking it.
+    fn try_resolve(&self) -> Resolution;
+    /// Blocks until the source is done. Called by `wait_all`; the default implementation polls
+    /// `try_resolve`.
+    fn wait(&self) {
+        while self.try_resolve() == Resolution::Pending {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    /// Blocks for at most `timeout`, returning whether the source is done. Called by
+    /// `wait_all_timeout`; the default implementation polls `try_resolve`.
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.try_resolve() != Resolution::Pending {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// What a `Resolveable` did when it was polled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// The result isn't available yet.
+    Pending,
+    /// The source settled its promise. Only these count as resolved tasks.
+    Settled,
+    /// The source is done without settling anything, e.g. because nobody holds its promise
+    /// anymore or its task went away without a result.
+    Dropped
+}
+
+/// Settles a promise with the first result sent on a channel.
+pub struct Running<T, E, M = Result<T, E>> {
+    receiver: Receiver<M>,
+    promise_state: Rc<RefCell<PromiseState<T, E>>>,
+    into_result: fn(M) -> Result<T, E>
+}
+
+impl<T: 'static, E: 'static> Running<T, E> {
+    pub fn new(receiver: Receiver<Result<T, E>>) -> (Promise<T, E>, Running<T, E>) {
+        let promise = Promise::<T, E>::with_state(PromiseState::Unresolved);
+        let running = Running { receiver, promise_state: promise.state.clone(), into_result: |result| result };
+        (promise, running)
+    }
+}
+
+impl<T, E, M> Running<T, E, M> {
+    // Nobody holds the promise and nothing is chained on it, so the result can't be observed.
+    fn is_abandoned(&self) -> bool {
+        Rc::strong_count(&self.promise_state) == 1 && self.promise_state.borrow().is_unresolved()
+    }
+}
+
+impl<T: 'static, E: 'static, M> Resolveable for Running<T, E, M> {
+    fn try_resolve(&self) -> Resolution {
+        match self.receiver.try_recv() {
+            Ok(message) => {
+                let _ = self.promise_state.settle((self.into_result)(message));
+                Resolution::Settled
+            },
+            Err(TryRecvError::Disconnected) => Resolution::Dropped,
+            Err(TryRecvError::Empty) if self.is_abandoned() => Resolution::Dropped,
+            Err(TryRecvError::Empty) => Resolution::Pending
+        }
+    }
+    fn wait(&self) {
+        if self.is_abandoned() {
+            return;
+        }
+        if let Ok(message) = self.receiver.recv() {
+            let _ = self.promise_state.settle((self.into_result)(message));
+        }
+    }
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        if self.is_abandoned() {
+            return true;
+        }
+        match self.receiver.recv_timeout(timeout) {
+            Ok(message) => {
+                let _ = self.promise_state.settle((self.into_result)(message));
+                true
+            },
+            Err(RecvTimeoutError::Disconnected) => true,
+            Err(RecvTimeoutError::Timeout) => false
+        }
+    }
+}
+
+// Resolves its promise once `at` has passed. It is polled by the runner like any other source
+// and never takes up a worker or a `max_concurrent` slot.
+// Setting `cancelled` drops the timer without resolving it, e.g. once a timeout lost its race.
+struct Timer {
+    at: Instant,
+    promise_state: Rc<RefCell<PromiseState<(), ()>>>,
+    cancelled: Rc<Cell<bool>>
+}
+
+impl Timer {
+    fn is_abandoned(&self) -> bool {
+        self.cancelled.get()
+            || (Rc::strong_count(&self.promise_state) == 1 && self.promise_state.borrow().is_unresolved())
+    }
+}
+
+impl Resolveable for Timer {
+    fn try_resolve(&self) -> Resolution {
+        if self.is_abandoned() {
+            return Resolution::Dropped;
+        }
+        if Instant::now() < self.at {
+            return Resolution::Pending;
+        }
+        let _ = self.promise_state.resolve(());
+        Resolution::Settled
+    }
+    fn wait(&self) {
+        if !self.is_abandoned() {
+            thread::sleep(self.at.saturating_duration_since(Instant::now()));
+            let _ = self.promise_state.resolve(());
+        }
+    }
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        let remaining = self.at.saturating_duration_since(Instant::now());
+        if self.is_abandoned() || remaining <= timeout {
+            self.wait();
+            return true;
+        }
+        thread::sleep(timeout);
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError;
+
+/// Rejects the promise of a task submitted to `AsyncRunner::exec_async_result` after the runner
+/// has been shut down. The task's error type has to be convertible from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutDown;
+
+impl From<ShutDown> for () {
+    fn from(_: ShutDown) {}
+}
+
+impl From<ShutDown> for String {
+    fn from(_: ShutDown) -> String {
+        "the runner has been shut down".to_string()
+    }
+}
+
+/// Cancels a task started with `AsyncRunner::exec_async_cancellable`.
+///
+/// A task that hasn't started yet is skipped, and the result of one that is already running is
+/// discarded. Either way its promise never settles.
+#[derive(Clone, Default)]
+pub struct TaskHandle {
+    cancelled: Arc<AtomicBool>
+}
+
+impl TaskHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+struct CancellableTask<T, E> {
+    running: Running<T, E>,
+    handle: TaskHandle
+}
+
+impl<T: 'static, E: 'static> Resolveable for CancellableTask<T, E> {
+    fn try_resolve(&self) -> Resolution {
+        if self.handle.is_cancelled() {
+            return Resolution::Dropped;
+        }
+        self.running.try_resolve()
+    }
+    fn wait(&self) {
+        if !self.handle.is_cancelled() {
+            self.running.wait();
+        }
+    }
+}
+
+/// What a single `AsyncRunner::poll` pass did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollOutcome {
+    pub resolved: usize,
+    pub pending: usize,
+    pub idle: bool
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+// Jobs whose input arrived through a promise callback, which has no access to the runner itself.
+type DeferredJobs = Rc<RefCell<VecDeque<(Job, Box<dyn Resolveable>)>>>;
+
+struct Tracked {
+    index: usize,
+    // False for sources adopted through `track`, which the runner can't know will ever finish.
+    owned: bool,
+    // Timers are waited on last, since the work they race against may cancel them.
+    timer: bool,
+    source: Box<dyn Resolveable>
+}
+
+// Reports a finished worker when dropped, so a panicking task still frees its slot.
+struct FinishedGuard(Sender<()>);
+
+impl Drop for FinishedGuard {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+pub trait Executor {
+    fn spawn(&self, f: Box<dyn FnOnce() + Send>);
+    // Executors that finish the task before `spawn` returns let the runner resolve the promise right away.
+    fn runs_inline(&self) -> bool {
+        false
+    }
+}
+
+pub struct InlineExecutor;
+
+impl Executor for InlineExecutor {
+    fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+        f();
+    }
+    fn runs_inline(&self) -> bool {
+        true
+    }
+}
+
+pub struct ThreadExecutor;
+
+impl Executor for ThreadExecutor {
+    fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+        thread::spawn(f);
+    }
+}
+
+impl Executor for ThreadPool {
+    fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+        self.execute(f);
+    }
+}
+
+impl Executor for Box<dyn Executor> {
+    fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+        (**self).spawn(f);
+    }
+    fn runs_inline(&self) -> bool {
+        (**self).runs_inline()
+    }
+}
+
+pub struct AsyncRunner<X: Executor = Box<dyn Executor>> {
+    running: Vec<Tracked>,
+    submitted: usize,
+    completed: Vec<usize>,
+    executor: X,
+    max_concurrent: Option<usize>,
+    active: usize,
+    queued: VecDeque<Job>,
+    finished_tx: Sender<()>,
+    finished_rx: Receiver<()>,
+    on_all_settled: Option<Box<dyn FnOnce()>>,
+    shut_down: bool,
+    throttled: VecDeque<(Duration, Job)>,
+    last_throttled: Option<Instant>,
+    deferred: DeferredJobs
+}
+impl AsyncRunner {
+    pub fn new() -> AsyncRunner {
+        AsyncRunner::with_executor(Box::new(ThreadExecutor))
+    }
+    pub fn new_pooled(threads: usize) -> AsyncRunner {
+        AsyncRunner::with_executor(Box::new(ThreadPool::new(threads)))
+    }
+    pub fn new_inline() -> AsyncRunner {
+        AsyncRunner::with_executor(Box::new(InlineExecutor))
+    }
+    pub fn builder() -> AsyncRunnerBuilder {
+        AsyncRunnerBuilder::default()
+    }
+}
+
+/// Configures an `AsyncRunner` before it is created. Without any options it builds the same
+/// thread-per-task runner as `AsyncRunner::new`.
+#[derive(Default)]
+pub struct AsyncRunnerBuilder {
+    executor: Option<Box<dyn Executor>>,
+    max_concurrent: Option<usize>
+}
+
+impl AsyncRunnerBuilder {
+    pub fn pool(self, threads: usize) -> AsyncRunnerBuilder {
+        self.executor(Box::new(ThreadPool::new(threads)))
+    }
+    pub fn inline(self) -> AsyncRunnerBuilder {
+        self.executor(Box::new(InlineExecutor))
+    }
+    pub fn executor(mut self, executor: Box<dyn Executor>) -> AsyncRunnerBuilder {
+        self.executor = Some(executor);
+        self
+    }
+    pub fn max_concurrent(mut self, max: usize) -> AsyncRunnerBuilder {
+        self.max_concurrent = Some(max);
+        self
+    }
+    pub fn build(self) -> AsyncRunner {
+        let mut runner = AsyncRunner::with_executor(self.executor.unwrap_or_else(|| Box::new(ThreadExecutor)));
+        if let Some(max) = self.max_concurrent {
+            runner.set_max_concurrent(max);
+        }
+        runner
+    }
+}
+
+impl<X: Executor> AsyncRunner<X> {
+    pub fn with_executor(executor: X) -> AsyncRunner<X> {
+        let (finished_tx, finished_rx) = mpsc::channel();
+        AsyncRunner {
+            running: vec![],
+            submitted: 0,
+            completed: vec![],
+            executor,
+            max_concurrent: None,
+            active: 0,
+            queued: VecDeque::new(),
+            finished_tx,
+            finished_rx,
+            on_all_settled: None,
+            shut_down: false,
+            throttled: VecDeque::new(),
+            last_throttled: None,
+            deferred: Rc::default()
+        }
+    }
+    pub fn set_max_concurrent(&mut self, max: usize) {
+        assert!(max >= 1, "max_concurrent must be at least 1.");
+        self.max_concurrent = Some(max);
+        self.launch_queued();
+    }
+    pub fn exec_async<T: Send + Sized + 'static, F: FnOnce() -> T + Send + Sized + 'static>(&mut self, run: F) -> Promise<T> {
+        // A panicking task rejects its promise instead of leaving it unresolved forever.
+        self.exec_async_result(move || panic::catch_unwind(AssertUnwindSafe(run)).map_err(|_| ()))
+    }
+    pub fn exec_async_result<T: Send + Sized + 'static, E: From<ShutDown> + Send + Sized + 'static, F: FnOnce() -> Result<T, E> + Send + Sized + 'static>(&mut self, run: F) -> Promise<T, E> {
+        if self.shut_down {
+            return Promise::rejected(E::from(ShutDown));
+        }
+        let (tx, rx) = mpsc::channel();
+
+        self.spawn(move || {
+            // The receiver is dropped once the promise is abandoned, in which case the value is discarded.
+            let _ = tx.send(run());
+        });
+
+        self.track_receiver(rx)
+    }
+    pub fn exec_async_cancellable<T: Send + 'static, F: FnOnce() -> T + Send + 'static>(&mut self, run: F) -> (Promise<T>, TaskHandle) {
+        let handle = TaskHandle::default();
+        if self.shut_down {
+            return (Promise::rejected(()), handle);
+        }
+        let (tx, rx) = mpsc::channel();
+        let worker_handle = handle.clone();
+        self.spawn(move || {
+            if worker_handle.is_cancelled() {
+                return;
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(run)).map_err(|_| ());
+            if !worker_handle.is_cancelled() {
+                let _ = tx.send(result);
+            }
+        });
+        let (promise, running) = Running::new(rx);
+        self.track_spawned(Box::new(CancellableTask { running, handle: handle.clone() }));
+        (promise, handle)
+    }
+    /// Throttled tasks start in submission order, each at least `min_interval` after the previous
+    /// throttled one started, also when `max_concurrent` held it back. Tasks that aren't due yet
+    /// wait in the runner until a later `try_resolve_all`.
+    ///
+    /// A due throttled task takes the next free `max_concurrent` slot ahead of any tasks queued
+    /// for one, even those submitted before it, so its interval isn't stretched by the queue.
+    pub fn exec_async_throttled<T: Send + 'static, F: FnOnce() -> T + Send + 'static>(&mut self, min_interval: Duration, run: F) -> Promise<T> {
+        if self.shut_down {
+            return Promise::rejected(());
+        }
+        let (tx, rx) = mpsc::channel();
+        self.throttled.push_back((min_interval, Box::new(move || {
+            let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(run)).map_err(|_| ()));
+        })));
+        self.launch_throttled();
+        self.track_receiver(rx)
+    }
+    pub fn map_async<I: Send + 'static, T: Send + 'static, F: Fn(I) -> T + Clone + Send + 'static>(&mut self, items: Vec<I>, f: F) -> Vec<Promise<T>> {
+        items.into_iter().map(|item| {
+            let f = f.clone();
+            self.exec_async(move || f(item))
+        }).collect()
+    }
+    pub fn exec_async_retry<T: Send + 'static, E: Send + 'static, F: Fn() -> Result<T, E> + Send + 'static>(&mut self, attempts: usize, run: F) -> Promise<Result<T, E>> {
+        assert!(attempts >= 1, "exec_async_retry needs at least one attempt.");
+        self.exec_async(move || {
+            let mut result = run();
+            for _ in 1..attempts {
+                if result.is_ok() {
+                    break;
+                }
+                result = run();
+            }
+            result
+        })
+    }
+    pub fn exec_async_with_progress<P: Send + 'static, T: Send + 'static, F: FnOnce(Sender<P>) -> T + Send + 'static>(&mut self, run: F) -> (Promise<T>, Receiver<P>) {
+        let (progress_tx, progress_rx) = mpsc::channel();
+        (self.exec_async(move || run(progress_tx)), progress_rx)
+    }
+    /// Sending progress blocks the worker once `bound` updates are waiting to be received.
+    pub fn exec_async_with_bounded_progress<P: Send + 'static, T: Send + 'static, F: FnOnce(SyncSender<P>) -> T + Send + 'static>(&mut self, bound: usize, run: F) -> (Promise<T>, Receiver<P>) {
+        let (progress_tx, progress_rx) = mpsc::sync_channel(bound);
+        (self.exec_async(move || run(progress_tx)), progress_rx)
+    }
+    /// The spawned closure can't hand back an `Rc`-based `Promise`, so it returns a `SendPromise`
+    /// instead, which may be resolved later from any thread; its value is then channeled back
+    /// to the runner like any other task result.
+    pub fn exec_async_promise<T: Send + 'static, F: FnOnce() -> SendPromise<T> + Send + 'static>(&mut self, run: F) -> Promise<T> {
+        if self.shut_down {
+            return Promise::rejected(());
+        }
+        let (tx, rx) = mpsc::channel();
+
+        self.spawn(move || {
+            match panic::catch_unwind(AssertUnwindSafe(run)) {
+                Ok(mut inner) => {
+                    inner.then_move(move |value| {
+                        let _ = tx.send(Ok(value));
+                    });
+                },
+                Err(_) => {
+                    let _ = tx.send(Err(()));
+                }
+            }
+        });
+
+        self.track_receiver(rx)
+    }
+    fn spawn<F: FnOnce() + Send + 'static>(&mut self, f: F) {
+        self.queued.push_back(Box::new(f));
+        self.launch_queued();
+    }
+    // Throttled jobs bypass `queued` and are only taken once a slot is free, so the spacing is
+    // measured between the moments they actually start.
+    fn launch_throttled(&mut self) {
+        while let Some(&(interval, _)) = self.throttled.front() {
+            let now = Instant::now();
+            if self.last_throttled.is_some_and(|last| now.duration_since(last) < interval) || !self.has_free_slot() {
+                break;
+            }
+            let (_, job) = self.throttled.pop_front().unwrap();
+            self.launch(job);
+            self.last_throttled = Some(Instant::now());
+        }
+    }
+    fn launch_queued(&mut self) {
+        let deferred: Vec<_> = self.deferred.borrow_mut().drain(..).collect();
+        let mut sources = Vec::with_capacity(deferred.len());
+        for (job, source) in deferred {
+            self.queued.push_back(job);
+            sources.push(source);
+        }
+        while !self.queued.is_empty() && self.has_free_slot() {
+            let job = self.queued.pop_front().unwrap();
+            self.launch(job);
+        }
+        for source in sources {
+            self.track_spawned(source);
+        }
+    }
+    fn has_free_slot(&mut self) -> bool {
+        while self.finished_rx.try_recv().is_ok() {
+            self.active -= 1;
+        }
+        self.max_concurrent.is_none_or(|max| self.active < max)
+    }
+    fn launch(&mut self, job: Job) {
+        let guard = FinishedGuard(self.finished_tx.clone());
+        let f = move || {
+            let _guard = guard;
+            job();
+        };
+        self.active += 1;
+        self.executor.spawn(Box::new(f));
+    }
+    fn track_receiver<T: 'static, E: 'static>(&mut self, receiver: Receiver<Result<T, E>>) -> Promise<T, E> {
+        let (promise, running) = Running::new(receiver);
+        self.track_spawned(Box::new(running));
+        promise
+    }
+    fn track_spawned(&mut self, source: Box<dyn Resolveable>) {
+        let index = self.submitted;
+        self.submitted += 1;
+        if !(self.executor.runs_inline() && source.try_resolve() != Resolution::Pending) {
+            self.running.push(Tracked { index, owned: true, timer: false, source });
+        }
+    }
+    fn track_timer(&mut self, ms: u64) -> (Promise<()>, Rc<Cell<bool>>) {
+        let cancelled = Rc::new(Cell::new(false));
+        if self.shut_down {
+            return (Promise::rejected(()), cancelled);
+        }
+        let promise = Promise::new();
+        let timer = Timer {
+            at: Instant::now() + Duration::from_millis(ms),
+            promise_state: promise.state.clone(),
+            cancelled: cancelled.clone()
+        };
+        let index = self.submitted;
+        self.submitted += 1;
+        self.running.push(Tracked { index, owned: true, timer: true, source: Box::new(timer) });
+        (promise, cancelled)
+    }
+    /// Adopts any resolution source, so the runner drives it along with the tasks it spawned.
+    /// `wait_all` blocks on adopted sources too, but dropping the runner doesn't: their sender may
+    /// live on, so they are dropped unresolved instead.
+    pub fn track(&mut self, source: Box<dyn Resolveable>) {
+        let index = self.submitted;
+        self.submitted += 1;
+        self.running.push(Tracked { index, owned: false, timer: false, source });
+    }
+    /// Refuses new tasks: `exec_async` and `exec_async_promise` return rejected promises from now
+    /// on, and `exec_async_result` rejects with `ShutDown`. Tasks already submitted still run to
+    /// completion.
+    pub fn shutdown(&mut self) {
+        self.shut_down = true;
+    }
+    /// Like `shutdown`, but also drops queued tasks that haven't started yet. Their promises never
+    /// settle, and running tasks can't be interrupted.
+    pub fn shutdown_now(&mut self) {
+        self.shutdown();
+        self.queued.clear();
+        self.throttled.clear();
+        self.deferred.borrow_mut().clear();
+    }
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down
+    }
+    pub fn delay(&mut self, ms: u64) -> Promise<()> {
+        self.track_timer(ms).0
+    }
+    pub fn with_timeout<T: 'static>(&mut self, mut promise: Promise<T>, ms: u64) -> Promise<Result<T, TimeoutError>> {
+        let mut work = promise.then_move(Ok);
+        let (mut timer, cancelled) = self.track_timer(ms);
+        let mut timer = timer.then_move(|_| Err(TimeoutError));
+        race(vec![&mut work, &mut timer]).finally(move || cancelled.set(true))
+    }
+    pub fn try_resolve_all(&mut self) -> usize {
+        self.try_resolve_some(usize::MAX)
+    }
+    /// Stops after `max` tasks have been resolved, leaving the rest for the next call.
+    pub fn try_resolve_some(&mut self, max: usize) -> usize {
+        self.launch_throttled();
+        self.launch_queued();
+        let running = mem::take(&mut self.running);
+        let count = running.len();
+        self.completed.clear();
+        for task in running {
+            if self.completed.len() >= max {
+                self.running.push(task);
+                continue;
+            }
+            match task.source.try_resolve() {
+                Resolution::Settled => self.completed.push(task.index),
+                Resolution::Dropped => {},
+                Resolution::Pending => self.running.push(task)
+            }
+        }
+        if count > 0 && self.is_idle() {
+            if let Some(f) = self.on_all_settled.take() {
+                f();
+            }
+        }
+        self.completed.len()
+    }
+    pub fn poll(&mut self) -> PollOutcome {
+        let resolved = self.try_resolve_all();
+        PollOutcome { resolved, pending: self.pending(), idle: self.is_idle() }
+    }
+    pub fn on_all_settled<F: FnOnce() + 'static>(&mut self, f: F) {
+        self.on_all_settled = Some(Box::new(f));
+    }
+    /// Tasks are identified by their submission index, starting at 0.
+    pub fn drain_completed(&mut self) -> Vec<usize> {
+        mem::take(&mut self.completed)
+    }
+    pub fn pending(&self) -> usize {
+        self.running.len() + self.deferred.borrow().len()
+    }
+    pub fn is_idle(&self) -> bool {
+        self.running.is_empty() && self.deferred.borrow().is_empty()
+    }
+    pub fn wait_all(&mut self) {
+        self.wait_tracked(None, true);
+    }
+    /// Returns false if the deadline passed first, leaving the unfinished tasks tracked.
+    pub fn wait_all_timeout(&mut self, timeout: Duration) -> bool {
+        self.wait_tracked(Some(Instant::now() + timeout), true)
+    }
+    fn wait_tracked(&mut self, deadline: Option<Instant>, adopted: bool) -> bool {
+        let remaining = || deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        self.launch_throttled();
+        while !self.throttled.is_empty() {
+            if remaining() == Some(Duration::from_millis(0)) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+            self.launch_throttled();
+        }
+        self.launch_queued();
+        while !self.queued.is_empty() {
+            let finished = match remaining() {
+                Some(timeout) => self.finished_rx.recv_timeout(timeout).is_ok(),
+                None => self.finished_rx.recv().is_ok()
+            };
+            if !finished {
+                return false;
+            }
+            self.active -= 1;
+            self.launch_queued();
+        }
+        let mut running = mem::take(&mut self.running);
+        running.sort_by_key(|task| task.timer);
+        for task in running {
+            let done = (task.owned || adopted) && match remaining() {
+                Some(timeout) => task.source.wait_timeout(timeout),
+                None => {
+                    task.source.wait();
+                    true
+                }
+            };
+            if !done {
+                self.running.push(task);
+            }
+        }
+        // Settling those tasks may have handed over `then_spawn` jobs, which need waiting on too.
+        if !self.deferred.borrow().is_empty() {
+            return self.wait_tracked(deadline, adopted);
+        }
+        self.running.is_empty()
+    }
+}
+
+impl Default for AsyncRunner {
+    fn default() -> AsyncRunner {
+        AsyncRunner::new()
+    }
+}
+
+impl<X: Executor> Drop for AsyncRunner<X> {
+    fn drop(&mut self) {
+        self.wait_tracked(None, false);
+    }
+}
+
+pub fn block_on<T: 'static, X: Executor>(promise: Promise<T>, runner: &mut AsyncRunner<X>) -> T {
+    while !promise.state.borrow().is_settled() {
+        if runner.is_idle() {
+            panic!("Trying to block on a promise that no task will resolve.");
+        }
+        if runner.try_resolve_all() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    if promise.state.borrow().is_rejected() {
+        panic!("Trying to block on a promise that was rejected.");
+    }
+    promise.into_value()
+}
+
+/// Asserts that `promise` is resolved to `expected`, telling pending, rejected and unequal
+/// promises apart in the failure message.
+#[cfg(any(test, feature = "testing"))]
+pub fn assert_resolved_eq<T: PartialEq + fmt::Debug + 'static, E: fmt::Debug + 'static>(promise: &Promise<T, E>, expected: T) {
+    if let Some(value) = promise.value() {
+        assert!(*value == expected, "Expected promise to resolve to {:?}, but it resolved to {:?}.", expected, *value);
+        return;
+    }
+    if let Some(err) = promise.error() {
+        panic!("Expected promise to resolve to {:?}, but it was rejected with {:?}.", expected, *err);
+    }
+    panic!("Expected promise to resolve to {:?}, but it is {:?}.", expected, promise);
+}
+
+/// A promise that can be moved to and resolved from another thread.
+///
+/// It is backed by `Arc<Mutex<..>>` instead of `Rc<RefCell<..>>`, so every access takes a lock,
+/// callbacks must be `Send`, and `value` hands out a clone rather than a borrow. It has no
+/// rejection channel. Prefer `Promise` with an `AsyncRunner` unless the resolving side really
+/// needs to live on another thread.
+pub struct SendPromise<T> {
+    state: Arc<Mutex<SendPromiseState<T>>>,
+    ready: Arc<Condvar>
+}
+
+impl<T: Send + 'static> SendPromise<T> {
+    pub fn new() -> SendPromise<T> {
+        SendPromise {
+            state: Arc::new(Mutex::new(SendPromiseState::Unresolved)),
+            ready: Arc::new(Condvar::new())
+        }
+    }
+    pub fn resolved(value: T) -> SendPromise<T> {
+        SendPromise {
+            state: Arc::new(Mutex::new(SendPromiseState::Resolved(value))),
+            ready: Arc::new(Condvar::new())
+        }
+    }
+    pub fn resolve(&mut self, value: T) {
+        // The state is checked and replaced under a single lock, and the lock is released before
+        // running the callback or panicking, so the mutex is never poisoned.
+        let mut s = self.state.lock().unwrap();
+        match mem::replace(&mut *s, SendPromiseState::Moved) {
+            SendPromiseState::Unresolved => {
+                *s = SendPromiseState::Resolved(value);
+                drop(s);
+                self.ready.notify_all();
+            },
+            SendPromiseState::ThenMove(transform) => {
+                drop(s);
+                // Waiters can't get the value anymore, but shouldn't sleep forever either.
+                self.ready.notify_all();
+                transform(value);
+            },
+            state => {
+                *s = state;
+                drop(s);
+                panic!("Trying to resolve a promise that has already been resolved.");
+            }
+        }
+    }
+    pub fn send_resolver(&self) -> SendResolver<T> {
+        SendResolver { promise: self.clone() }
+    }
+    pub fn value(&self) -> Option<T> where T: Clone {
+        match &*self.state.lock().unwrap() {
+            SendPromiseState::Resolved(value) => Some(value.clone()),
+            _ => None
+        }
+    }
+    /// Blocks the calling thread until the promise is resolved, e.g. through a `SendResolver`.
+    pub fn wait(&self) -> T where T: Clone {
+        let mut s = self.state.lock().unwrap();
+        loop {
+            if let SendPromiseState::Resolved(value) = &*s {
+                return value.clone();
+            }
+            if !matches!(*s, SendPromiseState::Unresolved) {
+                drop(s);
+                panic!("Trying to wait on a promise whose value has been moved.");
+            }
+            s = self.ready.wait(s).unwrap();
+        }
+    }
+    pub fn then_move<T2: Send + 'static, F: FnOnce(T) -> T2 + Send + 'static>(&mut self, transform: F) -> SendPromise<T2> {
+        let p = SendPromise::<T2>::new();
+        let mut p2 = p.clone();
+        let mut s = self.state.lock().unwrap();
+        match mem::replace(&mut *s, SendPromiseState::Moved) {
+            SendPromiseState::Unresolved => {
+                *s = SendPromiseState::ThenMove(Box::new(move |value| p2.resolve(transform(value))));
+            },
+            SendPromiseState::Resolved(value) => {
+                drop(s);
+                p2.resolve(transform(value));
+            },
+            state => {
+                *s = state;
+                drop(s);
+                panic!("Trying to move promise value that has already been moved.");
+            }
+        }
+        p
+    }
+}
+
+impl<T: Send + 'static> Default for SendPromise<T> {
+    fn default() -> SendPromise<T> {
+        SendPromise::new()
+    }
+}
+
+impl<T> Clone for SendPromise<T> {
+    fn clone(&self) -> SendPromise<T> {
+        SendPromise {
+            state: self.state.clone(),
+            ready: self.ready.clone()
+        }
+    }
+}
+
+/// A fire-once handle for resolving a `SendPromise` from another thread.
+///
+/// Resolving runs the promise's `then_move` callback, if any, right away on the resolving thread,
+/// and wakes up any thread blocked in `SendPromise::wait`.
+pub struct SendResolver<T> {
+    promise: SendPromise<T>
+}
+
+impl<T: Send + 'static> SendResolver<T> {
+    pub fn resolve(mut self, value: T) {
+        self.promise.resolve(value);
+    }
+}
+
+enum SendPromiseState<T> {
+    Unresolved,
+    Moved,
+    Resolved(T),
+    ThenMove(Box<dyn FnOnce(T) + Send>)
+}
+
+
+type ThenCallback<T, E> = Box<dyn FnOnce(Result<&T, &E>)>;
+type ThenMoveCallback<T, E> = Box<dyn FnOnce(Result<T, E>)>;
+
+enum PromiseState<T, E> {
+    Unresolved,
+    Moved,
+    Resolved(T),
+    Rejected(E),
+    // `insert_then` always pushes onto the outermost callback list and `insert_then_move` always
+    // goes to the tail, so borrowing callbacks fire in registration order and the single
+    // consuming callback fires last, regardless of how registrations were interleaved.
+    Then(Vec<ThenCallback<T, E>>, Box<PromiseState<T, E>>),
+    ThenMove(ThenMoveCallback<T, E>),
+    Lazy(Box<dyn FnOnce() -> T>)
+}
+
+impl<T, E> PromiseState<T, E> {
+    fn is_resolved(&self) -> bool {
+        matches!(self, PromiseState::Resolved(_))
+    }
+    fn is_rejected(&self) -> bool {
+        matches!(self, PromiseState::Rejected(_))
+    }
+    fn is_settled(&self) -> bool {
+        self.is_resolved() || self.is_rejected()
+    }
+    fn is_unresolved(&self) -> bool {
+        matches!(self, PromiseState::Unresolved)
+    }
+    fn is_lazy(&self) -> bool {
+        matches!(self, PromiseState::Lazy(_))
+    }
+    fn is_moved(&self) -> bool {
+        matches!(self, PromiseState::Moved)
+    }
+    fn callback_count(&self) -> usize {
+        let mut count = 0;
+        let mut state = self;
+        loop {
+            match state {
+                PromiseState::Then(ts, then) => {
+                    count += ts.len();
+                    state = then;
+                },
+                PromiseState::ThenMove(_) => return count + 1,
+                _ => return count
+            }
+        }
+    }
+    fn insert_then<F: FnOnce(Result<&T, &E>) + 'static>(self, transform: F) -> PromiseState<T, E> {
+        match self {
+            PromiseState::Unresolved => PromiseState::Then(vec![Box::new(transform)], Box::new(PromiseState::Unresolved)),
+            PromiseState::Then(mut ts, then) => {
+                ts.push(Box::new(transform));
+                PromiseState::Then(ts, then)
+            },
+            PromiseState::ThenMove(t) => {
+                PromiseState::Then(vec![Box::new(transform)], Box::new(PromiseState::ThenMove(t)))
+            },
+            _ => unreachable!()
+        }
+    }
+    fn insert_then_move<F: FnOnce(Result<T, E>) + 'static>(self, transform: F) -> PromiseState<T, E> {
+        let mut levels = vec![];
+        let mut state = self;
+        loop {
+            match state {
+                PromiseState::Unresolved => break,
+                PromiseState::Then(ts, then) => {
+                    levels.push(ts);
+                    state = *then;
+                },
+                PromiseState::ThenMove(_) => {
+                    panic!("Cannot move value out of promise twice.");
+                },
+                _ => unreachable!()
+            }
+        }
+        let mut state = PromiseState::ThenMove(Box::new(transform));
+        while let Some(ts) = levels.pop() {
+            state = PromiseState::Then(ts, Box::new(state));
+        }
+        state
+    }
+    // Moves the borrowing callbacks into `callbacks` in firing order and returns the consuming one.
+    fn into_callbacks(self, callbacks: &mut Vec<ThenCallback<T, E>>) -> Option<ThenMoveCallback<T, E>> {
+        let mut state = self;
+        loop {
+            match state {
+                PromiseState::Unresolved => return None,
+                PromiseState::Then(transforms, then) => {
+                    callbacks.extend(transforms);
+                    state = *then;
+                },
+                PromiseState::ThenMove(transform) => return Some(transform),
+                _ => unreachable!()
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug, E: fmt::Debug> fmt::Debug for PromiseState<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PromiseState::Unresolved => f.write_str("Unresolved"),
+            PromiseState::Moved => f.write_str("Moved"),
+            PromiseState::Resolved(value) => f.debug_tuple("Resolved").field(value).finish(),
+            PromiseState::Rejected(err) => f.debug_tuple("Rejected").field(err).finish(),
+            PromiseState::Then(ts, then) => match **then {
+                PromiseState::ThenMove(_) => write!(f, "Then({} callbacks, ThenMove)", ts.len()),
+                _ => write!(f, "Then({} callbacks)", ts.len())
+            },
+            PromiseState::ThenMove(_) => f.write_str("ThenMove"),
+            PromiseState::Lazy(_) => f.write_str("Lazy")
+        }
+    }
+}
+
+// Settling hands the result back as `Err` when the promise is already settled or moved. Chained
+// callbacks ignore that on purpose: their target was settled by hand first, e.g. through
+// `try_resolve`, and keeps that outcome.
+trait ResolvableState<T, E> {
+    fn settle(&self, result: Result<T, E>) -> Result<(), Result<T, E>>;
+    fn resolve(&self, value: T) -> Result<(), T> {
+        self.settle(Ok(value)).map_err(|result| result.ok().unwrap())
+    }
+    fn reject(&self, err: E) -> Result<(), E> {
+        self.settle(Err(err)).map_err(|result| result.err().unwrap())
+    }
+}
+type QueuedSettle = Box<dyn FnOnce()>;
+
+thread_local! {
+    // Settles queued while another promise's callbacks run. Draining them from the outermost
+    // settle keeps the stack flat however long a chain of promises gets.
+    static SETTLING: RefCell<Option<VecDeque<QueuedSettle>>> = const { RefCell::new(None) };
+}
+
+struct SettlingGuard;
+
+impl Drop for SettlingGuard {
+    fn drop(&mut self) {
+        SETTLING.with(|queue| *queue.borrow_mut() = None);
+    }
+}
+
+impl<T: 'static, E: 'static> ResolvableState<T, E> for Rc<RefCell<PromiseState<T, E>>> {
+    // The settled state is stored before any callback runs, so callbacks can read, chain on or
+    // try to resolve promises sharing this state. It stays borrowed while they run though, so
+    // moving the value out of a sibling from inside one panics.
+    //
+    // A promise with callbacks that is settled from inside another promise's callbacks is only
+    // settled once those return, and the consuming callback runs after everything the borrowing
+    // ones set off. If the promise was settled by hand in the meantime, the queued result is
+    // dropped.
+    //
+    // A lazy promise is still pending, so settling it drops the thunk without running it.
+    fn settle(&self, result: Result<T, E>) -> Result<(), Result<T, E>> {
+        {
+            let s = self.borrow();
+            if s.is_settled() || s.is_moved() {
+                return Err(result);
+            }
+            if s.is_unresolved() || s.is_lazy() {
+                drop(s);
+                *self.borrow_mut() = match result {
+                    Ok(value) => PromiseState::Resolved(value),
+                    Err(err) => PromiseState::Rejected(err)
+                };
+                return Ok(());
+            }
+        }
+        let state = self.clone();
+        if !queue_settle(Box::new(move || run_callbacks(&state, result))) {
+            return Ok(());
+        }
+        let _guard = SettlingGuard;
+        while let Some(next) = SETTLING.with(|queue| queue.borrow_mut().as_mut().and_then(VecDeque::pop_front)) {
+            next();
+        }
+        Ok(())
+    }
+}
+
+// Returns true if nothing was being settled yet, in which case the caller drains the queue.
+fn queue_settle(job: QueuedSettle) -> bool {
+    SETTLING.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        match &mut *queue {
+            Some(queue) => {
+                queue.push_back(job);
+                false
+            },
+            None => {
+                *queue = Some(VecDeque::from(vec![job]));
+                true
+            }
+        }
+    })
+}
+
+// The consuming callback is queued behind whatever the borrowing ones queued, so those still see
+// the value.
+fn run_callbacks<T: 'static, E: 'static>(state: &Rc<RefCell<PromiseState<T, E>>>, result: Result<T, E>) {
+    let pending = mem::replace(&mut *state.borrow_mut(), PromiseState::Unresolved);
+    if pending.is_settled() || pending.is_moved() {
+        *state.borrow_mut() = pending;
+        return;
+    }
+    let mut callbacks = vec![];
+    let then_move = pending.into_callbacks(&mut callbacks);
+    *state.borrow_mut() = match result {
+        Ok(value) => PromiseState::Resolved(value),
+        Err(err) => PromiseState::Rejected(err)
+    };
+    for callback in callbacks {
+        match &*state.borrow() {
+            PromiseState::Resolved(value) => callback(Ok(value)),
+            PromiseState::Rejected(err) => callback(Err(err)),
+            _ => unreachable!()
+        }
+    }
+    if let Some(then_move) = then_move {
+        let state = state.clone();
+        queue_settle(Box::new(move || {
+            match mem::replace(&mut *state.borrow_mut(), PromiseState::Moved) {
+                PromiseState::Resolved(value) => then_move(Ok(value)),
+                PromiseState::Rejected(err) => then_move(Err(err)),
+                _ => {}
+            }
+        }));
+    }
+}
+
+#[test]
+fn test_promise_resolve() {
+    let mut p = Promise::new();
+    p.resolve(5);
+    assert_eq!(*p.value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_resolved_then_promise() {
+    let mut p = Promise::resolved(5);
+    let p2 = p.then_promise(|val| Promise::resolved(val * 2));
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_then_promise() {
+    let mut p = Promise::new();
+    let p2 = p.then_promise(|val| Promise::resolved(val * 2));
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_resolved_then_move_promise() {
+    let mut p = Promise::resolved(5);
+    let p2 = p.then_move_promise(|val| Promise::resolved(val * 2));
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_then_move_promise() {
+    let mut p = Promise::new();
+    let p2 = p.then_move_promise(|val| Promise::resolved(val * 2));
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_then() {
+    let mut p = Promise::new();
+    let p2 = p.then(|val| val * 2);
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_then_after_resolved() {
+    let mut p = Promise::new();
+    p.resolve(7);
+    let p2 = p.then(|x| x * 2);
+    assert_eq!(*p2.value().unwrap(), 14);
+}
+
+#[test]
+fn test_promise_then_then_move() {
+    let mut p = Promise::new();
+    let p2 = p.then(|val| val * 2);
+    let p3 = p.then_move(|val| val * 3);
+    p.resolve(5);
+    assert!(p.value().is_none());
+    assert_eq!(*p2.value().unwrap(), 10);
+    assert_eq!(*p3.value().unwrap(), 15);
+}
+
+#[test]
+#[should_panic]
+fn test_promise_then_move_then_move() {
+    let mut p = Promise::<i32>::new();
+    p.then_move(|val| val * 2);
+    p.then_move(|val| val * 3);
+}
+
+#[test]
+#[should_panic]
+fn test_promise_resolved_then_move_then_move() {
+    let mut p = Promise::resolved(5);
+    p.then_move(|val| val * 2);
+    p.then_move(|val| val * 3);
+}
+
+#[test]
+#[should_panic]
+fn test_promise_resolved_then_move_then() {
+    let mut p = Promise::resolved(5);
+    p.then_move(|val| val * 2);
+    p.then(|val| val * 3);
+}
+
+#[test]
+fn test_promise_join() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<String> = Promise::new();
+    let j = (&mut a, &mut b).join().then(|(i, s)| format!("{} _ {}", i, s));
+    assert!(j.value().is_none());
+    a.resolve(5);
+    assert!(j.value().is_none());
+    b.resolve("hello".to_string());
+    assert_eq!(*j.value().unwrap(), "5 _ hello".to_string());
+}
+
+#[test]
+fn test_promise_join3() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<String> = Promise::new();
+    let mut c: Promise<String> = Promise::new();
+    let j = (&mut a, &mut b, &mut c).join().then(|(i, s, s2)| format!("{} _ {} {}", i, s, s2));
+    assert!(j.value().is_none());
+    a.resolve(5);
+    assert!(j.value().is_none());
+    b.resolve("hello".to_string());
+    c.resolve("world".to_string());
+    assert_eq!(*j.value().unwrap(), "5 _ hello world".to_string());
+}
+
+#[test]
+fn test_promise_array_join() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<i32> = Promise::new();
+    let j: Promise<Vec<i32>> = vec![&mut a, &mut b].join();
+    assert!(j.value().is_none());
+    a.resolve(5);
+    assert!(j.value().is_none());
+    b.resolve(7);
+    assert_eq!(*j.value().unwrap(), vec![5, 7]);
+}
+
+#[test]
+fn test_promise_async() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(|| {
+        thread::sleep(Duration::from_millis(10));
+        "Hello world from thread".to_string()
+    });
+    runner.try_resolve_all();
+    assert!(p.value().is_none());
+    thread::sleep(Duration::from_millis(20));
+    runner.try_resolve_all();
+    assert_eq!(*p.value().unwrap(), "Hello world from thread");
+}
+
+#[test]
+fn test_promise_rejected() {
+    let p = Promise::<i32, String>::rejected("failed".to_string());
+    assert!(p.value().is_none());
+    assert_eq!(*p.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_promise_rejected_then() {
+    let mut p = Promise::<i32, String>::rejected("failed".to_string());
+    let p2 = p.then(|val| val * 2);
+    assert!(p2.value().is_none());
+    assert_eq!(*p2.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_promise_rejected_then_move_promise() {
+    let mut p = Promise::<i32, String>::rejected("failed".to_string());
+    let p2 = p.then_move_promise(|val| Promise::rejected(format!("transformed {}", val)))
+        .then_move(|val: i32| val + 1);
+    assert!(p2.value().is_none());
+    assert_eq!(*p2.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_promise_catch_rejected() {
+    let mut p = Promise::<i32, String>::rejected("failed".to_string());
+    let p2 = p.catch(|err| err.len() as i32);
+    assert_eq!(*p2.value().unwrap(), 6);
+}
+
+#[test]
+fn test_promise_catch_resolved() {
+    let mut p = Promise::resolved(5);
+    let p2 = p.catch(|_| 0);
+    assert_eq!(*p2.value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_catch_pending() {
+    let mut p = Promise::new();
+    let p2 = p.then_move_promise(|_: i32| Promise::rejected(())).catch(|_| 10);
+    assert!(p2.value().is_none());
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_then_result_ok() {
+    let mut p = Promise::resolved(5);
+    let p2 = p.then_result(|val| Ok(val * 2));
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_then_result_err() {
+    let mut p = Promise::resolved(5);
+    let mut p2 = p.then_result(|val| if *val > 3 { Err(()) } else { Ok(*val) });
+    let p3 = p2.then(|val| val * 2);
+    assert!(p2.value().is_none());
+    assert!(p2.error().is_some());
+    assert!(p3.value().is_none());
+    assert!(p3.error().is_some());
+}
+
+#[test]
+fn test_promise_then_result_catch() {
+    let mut p = Promise::new();
+    let p2 = p.then_result(|val: &String| val.parse::<i32>().map_err(|_| ())).catch(|_| -1);
+    assert!(p2.value().is_none());
+    p.resolve("not a number".to_string());
+    assert_eq!(*p2.value().unwrap(), -1);
+}
+
+#[test]
+fn test_promise_pending() {
+    let mut p = Promise::<i32, String>::pending();
+    assert!(p.value().is_none() && p.error().is_none());
+    p.reject("failed".to_string());
+    assert_eq!(*p.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_promise_map_err_rejected() {
+    let mut p = Promise::<i32, i32>::rejected(404);
+    let p2 = p.map_err(|code| format!("error {}", code));
+    assert_eq!(*p2.error().unwrap(), "error 404");
+}
+
+#[test]
+fn test_promise_map_err_resolved() {
+    let mut p = Promise::new();
+    let p2: Promise<Vec<i32>, String> = p.map_err(|_| "unused".to_string());
+    p.resolve(vec![1, 2]);
+    assert!(p2.error().is_none());
+    assert_eq!(*p2.value().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn test_promise_finally() {
+    let calls = Rc::new(Cell::new(0));
+    let calls2 = calls.clone();
+    let mut p = Promise::new();
+    let p2 = p.then(|val| val * 2);
+    let p3 = p.finally(move || calls2.set(calls2.get() + 1));
+    assert_eq!(calls.get(), 0);
+    p.resolve(5);
+    assert_eq!(calls.get(), 1);
+    assert_eq!(*p2.value().unwrap(), 10);
+    assert_eq!(*p3.value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_finally_rejected() {
+    let calls = Rc::new(Cell::new(0));
+    let calls2 = calls.clone();
+    let mut p = Promise::<i32, String>::rejected("failed".to_string());
+    let p2 = p.finally(move || calls2.set(calls2.get() + 1));
+    assert_eq!(calls.get(), 1);
+    assert_eq!(*p2.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_promise_race() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<i32> = Promise::new();
+    let r = race(vec![&mut a, &mut b]);
+    assert!(r.value().is_none());
+    b.resolve(7);
+    assert_eq!(*r.value().unwrap(), 7);
+    a.resolve(5);
+    assert_eq!(*r.value().unwrap(), 7);
+}
+
+#[test]
+fn test_promise_race_already_resolved() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b = Promise::resolved(7);
+    let r = race(vec![&mut a, &mut b]);
+    assert_eq!(*r.value().unwrap(), 7);
+    a.resolve(5);
+    assert_eq!(*r.value().unwrap(), 7);
+}
+
+#[test]
+#[should_panic]
+fn test_promise_race_empty() {
+    race::<i32, ()>(vec![]);
+}
+
+#[test]
+fn test_promise_any() {
+    let mut a = Promise::<i32>::rejected(());
+    let mut b: Promise<i32> = Promise::new();
+    let r = any(vec![&mut a, &mut b]);
+    assert!(r.value().is_none());
+    assert!(r.error().is_none());
+    b.resolve(7);
+    assert_eq!(*r.value().unwrap(), 7);
+}
+
+#[test]
+fn test_promise_any_all_rejected() {
+    let mut a = Promise::<i32, String>::rejected("a".to_string());
+    let mut b = Promise::<i32, String>::rejected("b".to_string());
+    let r = any(vec![&mut a, &mut b]);
+    assert_eq!(*r.error().unwrap(), vec!["a".to_string(), "b".to_string()]);
+}
+
+#[test]
+fn test_promise_all_settled() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b_source: Promise<i32> = Promise::new();
+    let mut b = b_source.then_result(|_| Err(()));
+    let mut c = Promise::resolved(3);
+    let r = all_settled(vec![&mut a, &mut b, &mut c]);
+    assert!(r.value().is_none());
+    b_source.resolve(2);
+    assert!(r.value().is_none());
+    a.resolve(1);
+    assert_eq!(*r.value().unwrap(), vec![Ok(1), Err(()), Ok(3)]);
+}
+
+#[test]
+fn test_promise_array_join_out_of_order() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<i32> = Promise::new();
+    let mut c: Promise<i32> = Promise::new();
+    let j: Promise<Vec<i32>> = vec![&mut a, &mut b, &mut c].join();
+    b.resolve(7);
+    c.resolve(9);
+    assert!(j.value().is_none());
+    a.resolve(5);
+    assert_eq!(*j.value().unwrap(), vec![5, 7, 9]);
+}
+
+#[test]
+fn test_promise_join4() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<String> = Promise::new();
+    let mut c: Promise<String> = Promise::new();
+    let mut d: Promise<bool> = Promise::new();
+    let j = join4(&mut a, &mut b, &mut c, &mut d).then(|(i, s, s2, b)| format!("{} _ {} {} {}", i, s, s2, b));
+    assert!(j.value().is_none());
+    a.resolve(5);
+    d.resolve(true);
+    assert!(j.value().is_none());
+    c.resolve("world".to_string());
+    b.resolve("hello".to_string());
+    assert_eq!(*j.value().unwrap(), "5 _ hello world true".to_string());
+}
+
+#[test]
+fn test_promise_join5() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<String> = Promise::new();
+    let mut c: Promise<String> = Promise::new();
+    let mut d: Promise<bool> = Promise::new();
+    let mut e: Promise<char> = Promise::new();
+    let j = (&mut a, &mut b, &mut c, &mut d, &mut e).join().then(|(i, s, s2, b, ch)| format!("{} _ {} {} {} {}", i, s, s2, b, ch));
+    assert!(j.value().is_none());
+    e.resolve('x');
+    a.resolve(5);
+    b.resolve("hello".to_string());
+    c.resolve("world".to_string());
+    assert!(j.value().is_none());
+    d.resolve(false);
+    assert_eq!(*j.value().unwrap(), "5 _ hello world false x".to_string());
+}
+
+#[test]
+fn test_promise_join6() {
+    let mut a = Promise::resolved(1);
+    let mut b = Promise::resolved(2);
+    let mut c = Promise::resolved(3);
+    let mut d = Promise::resolved(4);
+    let mut e = Promise::resolved(5);
+    let mut f: Promise<i32> = Promise::new();
+    let j = join6(&mut a, &mut b, &mut c, &mut d, &mut e, &mut f);
+    assert!(j.value().is_none());
+    f.resolve(6);
+    assert_eq!(*j.value().unwrap(), (1, 2, 3, 4, 5, 6));
+}
+
+#[test]
+fn test_promise_fixed_array_join() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<i32> = Promise::new();
+    let mut c: Promise<i32> = Promise::new();
+    let j: Promise<[i32; 3]> = [&mut a, &mut b, &mut c].join();
+    c.resolve(9);
+    a.resolve(5);
+    assert!(j.value().is_none());
+    b.resolve(7);
+    assert_eq!(*j.value().unwrap(), [5, 7, 9]);
+}
+
+#[test]
+fn test_promise_map_aliases() {
+    let mut p = Promise::new();
+    let p2 = p.map_ref(|val| val * 2);
+    let p3 = p.map(|val| val * 3).and_then(|val| Promise::resolved(val + 1));
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 10);
+    assert_eq!(*p3.value().unwrap(), 16);
+}
+
+#[test]
+fn test_promise_inspect() {
+    let seen = Rc::new(Cell::new(0));
+    let seen2 = seen.clone();
+    let mut p = Promise::new();
+    let p2 = p.inspect(move |val| seen2.set(*val));
+    assert_eq!(seen.get(), 0);
+    p.resolve(5);
+    assert_eq!(seen.get(), 5);
+    assert_eq!(*p2.value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_flatten_resolved() {
+    let p = Promise::resolved(Promise::resolved(5));
+    assert_eq!(*p.flatten().value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_flatten() {
+    let mut outer = Promise::new();
+    let mut inner = Promise::new();
+    let inner_handle = inner.clone();
+    let p = outer.then_move(move |_: ()| inner_handle).flatten();
+    outer.resolve(());
+    assert!(p.value().is_none());
+    inner.resolve(5);
+    assert_eq!(*p.value().unwrap(), 5);
+}
+
+#[test]
+fn test_async_runner_drop_drains_tasks() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(|| {
+        thread::sleep(Duration::from_millis(10));
+        5
+    });
+    assert!(p.value().is_none());
+    drop(runner);
+    assert_eq!(*p.value().unwrap(), 5);
+}
+
+#[test]
+fn test_async_runner_abandoned_promise() {
+    struct Guard(Sender<bool>);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.send(thread::panicking()).unwrap();
+        }
+    }
+    let (done_tx, done_rx) = mpsc::channel();
+    let guard = Guard(done_tx);
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(move || {
+        let _guard = &guard;
+        thread::sleep(Duration::from_millis(20));
+        5
+    });
+    drop(p);
+    assert_eq!(runner.try_resolve_all(), 0);
+    assert!(runner.drain_completed().is_empty());
+    assert!(runner.is_idle());
+    assert!(!done_rx.recv().unwrap());
+}
+
+#[test]
+fn test_async_runner_wait_all() {
+    let mut runner = AsyncRunner::new_pooled(2);
+    let promises: Vec<Promise<u32>> = (0..4).map(|i| runner.exec_async(move || {
+        thread::sleep(Duration::from_millis(5 * i as u64));
+        i * 2
+    })).collect();
+    runner.wait_all();
+    let values: Vec<u32> = promises.iter().map(|p| *p.value().unwrap()).collect();
+    assert_eq!(values, vec![0, 2, 4, 6]);
+}
+
+#[test]
+fn test_async_runner_try_resolve_all_count() {
+    let mut runner = AsyncRunner::new();
+    let _fast = runner.exec_async(|| 1);
+    let _slow = runner.exec_async(|| {
+        thread::sleep(Duration::from_millis(50));
+        2
+    });
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(runner.try_resolve_all(), 1);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(runner.try_resolve_all(), 1);
+    assert_eq!(runner.try_resolve_all(), 0);
+}
+
+#[test]
+fn test_async_runner_pending() {
+    let mut runner = AsyncRunner::new();
+    assert!(runner.is_idle());
+    let (go_tx, go_rx) = mpsc::channel();
+    let _fast = runner.exec_async(|| 1);
+    let _slow = runner.exec_async(move || {
+        go_rx.recv().unwrap();
+        2
+    });
+    assert_eq!(runner.pending(), 2);
+    thread::sleep(Duration::from_millis(20));
+    runner.try_resolve_all();
+    assert_eq!(runner.pending(), 1);
+    go_tx.send(()).unwrap();
+    runner.wait_all();
+    assert_eq!(runner.pending(), 0);
+    assert!(runner.is_idle());
+}
+
+#[test]
+fn test_promise_async_fn_once() {
+    struct Owned(String);
+    let owned = Owned("moved into task".to_string());
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(move || {
+        let Owned(s) = owned;
+        s
+    });
+    runner.wait_all();
+    assert_eq!(*p.value().unwrap(), "moved into task");
+}
+
+#[test]
+fn test_promise_async_result() {
+    let mut runner = AsyncRunner::new();
+    let ok = runner.exec_async_result(|| Ok::<i32, String>(5));
+    let err = runner.exec_async_result(|| Err::<i32, String>("failed".to_string()));
+    thread::sleep(Duration::from_millis(20));
+    runner.try_resolve_all();
+    assert_eq!(*ok.value().unwrap(), 5);
+    assert!(err.value().is_none());
+    assert_eq!(*err.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_promise_async_panic() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(|| -> i32 { panic!("Task failed") });
+    runner.wait_all();
+    assert!(runner.is_idle());
+    assert!(p.value().is_none());
+    assert!(p.error().is_some());
+}
+
+#[test]
+fn test_promise_future() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+    struct Flag(AtomicBool);
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut p = Promise::new();
+    let mut future = p.then(|val| val * 2);
+    for _ in 0..100 {
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+    }
+    assert_eq!(future.callback_count(), 1);
+    assert!(!flag.0.load(Ordering::SeqCst));
+    p.resolve(5);
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Ok(10)));
+}
+
+#[test]
+fn test_block_on() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(|| {
+        thread::sleep(Duration::from_millis(10));
+        "Hello world from thread".to_string()
+    });
+    assert_eq!(block_on(p, &mut runner), "Hello world from thread");
+}
+
+#[test]
+#[should_panic]
+fn test_block_on_unresolvable() {
+    let mut runner = AsyncRunner::new();
+    block_on(Promise::<i32>::new(), &mut runner);
+}
+
+#[test]
+#[should_panic(expected = "rejected")]
+fn test_block_on_rejected() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(|| -> i32 { panic!("task failed") });
+    block_on(p, &mut runner);
+}
+
+#[test]
+fn test_send_promise_resolve_from_thread() {
+    let mut p: SendPromise<i32> = SendPromise::new();
+    let p2 = p.then_move(|val| val * 2);
+    let mut resolver = p.clone();
+    thread::spawn(move || resolver.resolve(5)).join().unwrap();
+    assert_eq!(p2.value(), Some(10));
+    assert_eq!(p.value(), None);
+}
+
+#[test]
+fn test_send_promise_resolved_then_move() {
+    let mut p = SendPromise::resolved(5);
+    assert_eq!(p.value(), Some(5));
+    let p2 = p.then_move(|val| val.to_string());
+    assert_eq!(p2.value(), Some("5".to_string()));
+}
+
+#[test]
+fn test_promise_value_resolved() {
+    let p = Promise::resolved("value".to_string());
+    assert_eq!(*p.value().unwrap(), "value");
+    assert_eq!(*p.value().unwrap(), "value");
+}
+
+#[test]
+fn test_promise_value_unresolved() {
+    let mut p = Promise::<i32>::new();
+    assert!(p.value().is_none());
+    p.then_move(|val| val);
+    assert!(p.value().is_none());
+}
+
+#[test]
+fn test_promise_deep_chain() {
+    let mut p = Promise::new();
+    let mut tail = p.then_move(|val: u64| val + 1);
+    for _ in 1..100000 {
+        tail = tail.then_move(|val| val + 1);
+    }
+    let mut borrowed = tail.then(|val| val * 2);
+    for _ in 1..100000 {
+        borrowed = borrowed.then(|val| val + 1);
+    }
+    p.resolve(0);
+    assert_eq!(*tail.value().unwrap(), 100000);
+    assert_eq!(*borrowed.value().unwrap(), 200000 + 99999);
+}
+
+#[test]
+fn test_promise_is_pending() {
+    let mut p = Promise::new();
+    assert!(p.is_pending());
+    assert!(!p.is_resolved());
+    p.resolve(5);
+    assert!(!p.is_pending());
+    assert!(p.is_resolved());
+    assert!(!p.is_rejected());
+}
+
+#[test]
+fn test_promise_is_rejected() {
+    let p = Promise::<i32, String>::rejected("failed".to_string());
+    assert!(!p.is_pending());
+    assert!(!p.is_resolved());
+    assert!(p.is_rejected());
+}
+
+#[test]
+fn test_promise_is_pending_moved() {
+    let mut p = Promise::new();
+    p.then_move(|val: i32| val);
+    assert!(p.is_pending());
+    p.resolve(5);
+    assert!(!p.is_pending());
+    assert!(!p.is_resolved());
+    assert!(!p.is_rejected());
+    let mut p2 = Promise::resolved(5);
+    p2.then_move(|val| val);
+    assert!(!p2.is_pending());
+    assert!(!p2.is_resolved());
+}
+
+#[test]
+fn test_promise_clone() {
+    let mut p = Promise::new();
+    let p2 = p.clone();
+    assert!(p2.value().is_none());
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 5);
+    assert_eq!(p2.into_value(), 5);
+    assert!(p.value().is_none());
+}
+
+#[test]
+fn test_promise_debug() {
+    let mut p = Promise::<i32>::new();
+    assert_eq!(format!("{:?}", p), "Promise(Unresolved)");
+    p.then(|val| val * 2);
+    p.then(|val| val * 3);
+    assert_eq!(format!("{:?}", p), "Promise(Then(2 callbacks))");
+    p.then_move(|val| val);
+    assert_eq!(format!("{:?}", p), "Promise(Then(2 callbacks, ThenMove))");
+    p.resolve(5);
+    assert_eq!(format!("{:?}", p), "Promise(Moved)");
+    assert_eq!(format!("{:?}", Promise::resolved(5)), "Promise(Resolved(5))");
+    assert_eq!(format!("{:?}", Promise::<i32, &str>::rejected("failed")), "Promise(Rejected(\"failed\"))");
+}
+
+#[test]
+fn test_promise_into_value_opt() {
+    assert_eq!(Promise::resolved(5).into_value_opt(), Some(5));
+    assert_eq!(Promise::<i32>::new().into_value_opt(), None);
+    let mut p = Promise::resolved(5);
+    p.then_move(|val| val);
+    assert_eq!(p.into_value_opt(), None);
+}
+
+#[test]
+fn test_promise_try_into_value() {
+    assert_eq!(Promise::resolved(5).try_into_value().ok(), Some(5));
+    let mut p = Promise::new();
+    let p2 = p.then(|val| val * 2);
+    let mut p = p.try_into_value().unwrap_err();
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 10);
+    assert_eq!(p.try_into_value().ok(), Some(5));
+}
+
+#[test]
+fn test_async_runner_delay() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.delay(20);
+    runner.try_resolve_all();
+    assert!(p.value().is_none());
+    thread::sleep(Duration::from_millis(40));
+    runner.try_resolve_all();
+    assert!(p.value().is_some());
+
+    // An inline runner doesn't sleep in `delay`, and a full `max_concurrent` slot doesn't hold
+    // the timer up.
+    let mut runner = AsyncRunner::new_inline();
+    let start = Instant::now();
+    let p = runner.delay(20);
+    assert!(start.elapsed() < Duration::from_millis(20));
+    block_on(p, &mut runner);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    let mut runner = AsyncRunner::builder().max_concurrent(1).build();
+    let (tx, rx) = mpsc::channel::<()>();
+    let blocked = runner.exec_async(move || rx.recv().unwrap());
+    let p = runner.delay(10);
+    block_on(p, &mut runner);
+    tx.send(()).unwrap();
+    block_on(blocked, &mut runner);
+}
+
+#[test]
+fn test_async_runner_with_timeout() {
+    let mut runner = AsyncRunner::new();
+    let slow = runner.exec_async(|| {
+        thread::sleep(Duration::from_millis(100));
+        1
+    });
+    let slow = runner.with_timeout(slow, 10);
+    assert_eq!(block_on(slow, &mut runner), Err(TimeoutError));
+    let fast = runner.exec_async(|| 2);
+    let fast = runner.with_timeout(fast, 100);
+    assert_eq!(block_on(fast, &mut runner), Ok(2));
+
+    // Once the work wins, the timer is dropped instead of holding up `wait_all`.
+    let start = Instant::now();
+    let fast = runner.exec_async(|| 3);
+    let fast = runner.with_timeout(fast, 10_000);
+    runner.wait_all();
+    assert!(start.elapsed() < Duration::from_millis(5_000));
+    assert_eq!(fast.into_value_opt(), Some(Ok(3)));
+
+    // Neither an inline runner nor a full `max_concurrent` slot delays the timeout.
+    let mut runner = AsyncRunner::new_inline();
+    let start = Instant::now();
+    let fast = runner.exec_async(|| 4);
+    let fast = runner.with_timeout(fast, 10_000);
+    assert_eq!(block_on(fast, &mut runner), Ok(4));
+    assert!(start.elapsed() < Duration::from_millis(5_000));
+
+    let mut runner = AsyncRunner::builder().max_concurrent(1).build();
+    let slow = runner.exec_async(|| {
+        thread::sleep(Duration::from_millis(100));
+        5
+    });
+    let slow = runner.with_timeout(slow, 10);
+    assert_eq!(block_on(slow, &mut runner), Err(TimeoutError));
+}
+
+#[test]
+fn test_promise_then_cancelled() {
+    let calls = Rc::new(Cell::new(0));
+    let calls2 = calls.clone();
+    let token = CancelToken::new();
+    let mut p = Promise::new();
+    let p2 = p.then_cancellable(&token, move |val| {
+        calls2.set(calls2.get() + 1);
+        val * 2
+    });
+    let p3 = p.then_move_cancellable(&token, |val| val * 3);
+    token.cancel();
+    p.resolve(5);
+    assert_eq!(calls.get(), 0);
+    assert!(p2.error().is_some());
+    assert!(p3.error().is_some());
+
+    // Waiting on the downstream promise doesn't hang once its callback is cancelled.
+    let token = CancelToken::new();
+    let mut p = Promise::new();
+    let mut p2 = p.then_cancellable(&token, |val| val * 2);
+    let mut p3 = p.then_cancellable(&token, |val| val * 3);
+    let both = join(&mut p2, &mut p3);
+    token.cancel();
+    p.resolve(5);
+    assert!(both.error().is_some());
+
+    let token = CancelToken::new();
+    let mut p = Promise::<i32, String>::pending();
+    let p2 = p.then_move_cancellable(&token, |val| val * 2);
+    token.cancel();
+    p.resolve(5);
+    assert_eq!(*p2.error().unwrap(), String::from(Cancelled));
+}
+
+#[test]
+fn test_promise_then_cancelled_after_resolve() {
+    let token = CancelToken::new();
+    let mut p = Promise::new();
+    let p2 = p.then_cancellable(&token, |val| val * 2);
+    p.resolve(5);
+    token.cancel();
+    assert_eq!(*p2.value().unwrap(), 10);
+    let p3 = p.then_move_cancellable(&token, |val| val * 3);
+    assert!(p3.error().is_some());
+}
+
+#[test]
+fn test_async_runner_exec_async_promise() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async_promise(|| {
+        let inner = SendPromise::new();
+        let mut resolver = inner.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            resolver.resolve(5);
+        });
+        inner
+    });
+    assert_eq!(block_on(p, &mut runner), 5);
+}
+
+#[test]
+fn test_async_runner_max_concurrent() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let current = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let mut runner = AsyncRunner::new();
+    runner.set_max_concurrent(2);
+    let promises: Vec<Promise<usize>> = (0..6).map(|i| {
+        let current = current.clone();
+        let peak = peak.clone();
+        runner.exec_async(move || {
+            let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            current.fetch_sub(1, Ordering::SeqCst);
+            i
+        })
+    }).collect();
+    runner.wait_all();
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+    let values: Vec<usize> = promises.iter().map(|p| *p.value().unwrap()).collect();
+    assert_eq!(values, vec![0, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_promise_from() {
+    let p: Promise<i32> = 5.into();
+    assert_eq!(*p.value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_default() {
+    #[derive(Default)]
+    struct Holder {
+        promise: Promise<i32>
+    }
+    assert!(Promise::<i32>::default().is_pending());
+    assert!(Holder::default().promise.is_pending());
+}
+
+#[test]
+fn test_promise_join_all() {
+    let mut a: Promise<i32> = Promise::new();
+    let j = join_all(vec![a.clone(), Promise::resolved(7)]);
+    assert!(j.value().is_none());
+    a.resolve(5);
+    assert_eq!(*j.value().unwrap(), vec![5, 7]);
+}
+
+#[test]
+fn test_promise_race_all() {
+    let a: Promise<i32> = Promise::new();
+    let mut b: Promise<i32> = Promise::new();
+    let r = race_all(vec![a.clone(), b.clone()]);
+    b.resolve(7);
+    assert_eq!(*r.value().unwrap(), 7);
+}
+
+#[test]
+fn test_promise_map_each() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<i32> = Promise::new();
+    let j = vec![&mut a, &mut b].join().map_each(|x| x.to_string());
+    a.resolve(5);
+    b.resolve(7);
+    assert_eq!(*j.value().unwrap(), vec!["5".to_string(), "7".to_string()]);
+}
+
+#[test]
+fn test_promise_fold() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<i32> = Promise::new();
+    let mut c: Promise<i32> = Promise::new();
+    let digits = fold(vec![&mut a, &mut b, &mut c], String::new(), |acc, x| format!("{}{}", acc, x));
+    c.resolve(3);
+    a.resolve(1);
+    assert!(digits.value().is_none());
+    b.resolve(2);
+    assert_eq!(*digits.value().unwrap(), "123");
+}
+
+#[test]
+fn test_promise_fold_sum() {
+    let mut promises: Vec<Promise<i32>> = (1..5).map(Promise::resolved).collect();
+    let sum = fold(promises.iter_mut().collect(), 0, |acc, x| acc + x);
+    assert_eq!(*sum.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_fold_empty() {
+    let p = fold(Vec::<&mut Promise<i32>>::new(), 10, |acc, x| acc + x);
+    assert_eq!(*p.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_select() {
+    let mut a: Promise<&str> = Promise::new();
+    let mut b: Promise<&str> = Promise::new();
+    let r = select(vec![&mut a, &mut b]);
+    b.resolve("b");
+    a.resolve("a");
+    assert_eq!(*r.value().unwrap(), (1, "b"));
+}
+
+#[test]
+fn test_promise_lazy() {
+    let calls = Rc::new(Cell::new(0));
+    let calls2 = calls.clone();
+    let mut p = Promise::lazy(move || {
+        calls2.set(calls2.get() + 1);
+        5
+    });
+    assert!(p.is_pending());
+    assert_eq!(calls.get(), 0);
+    let p2 = p.then(|val| val * 2);
+    assert_eq!(calls.get(), 1);
+    assert_eq!(*p.value().unwrap(), 5);
+    assert_eq!(*p2.value().unwrap(), 10);
+    assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn test_promise_lazy_resolve() {
+    let calls = Rc::new(Cell::new(0));
+    let calls2 = calls.clone();
+    let mut p = Promise::lazy(move || {
+        calls2.set(calls2.get() + 1);
+        5
+    });
+    assert!(p.is_pending());
+    p.resolve(7);
+    assert!(!p.is_pending());
+    assert_eq!(*p.value().unwrap(), 7);
+    assert_eq!(p.try_resolve(9), Err(9));
+    assert_eq!(calls.get(), 0);
+}
+
+#[test]
+fn test_promise_lazy_value() {
+    let p = Promise::lazy(|| "computed".to_string());
+    assert_eq!(format!("{:?}", p), "Promise(Lazy)");
+    assert_eq!(*p.value().unwrap(), "computed");
+}
+
+#[test]
+fn test_promise_on_resolve() {
+    let seen = Rc::new(RefCell::new(vec![]));
+    let seen1 = seen.clone();
+    let seen2 = seen.clone();
+    let mut p = Promise::new();
+    p.on_resolve(move |val| seen1.borrow_mut().push(*val));
+    p.on_resolve(move |val| seen2.borrow_mut().push(*val * 2));
+    assert!(seen.borrow().is_empty());
+    p.resolve(5);
+    assert_eq!(*seen.borrow(), vec![5, 10]);
+}
+
+#[test]
+fn test_promise_then_order() {
+    let order = Rc::new(RefCell::new(vec![]));
+    let mut p = Promise::new();
+    let o = order.clone();
+    p.on_resolve(move |_| o.borrow_mut().push("then 1"));
+    let o = order.clone();
+    p.then_move(move |_: i32| o.borrow_mut().push("then_move"));
+    let o = order.clone();
+    p.on_resolve(move |_| o.borrow_mut().push("then 2"));
+    let o = order.clone();
+    p.then(move |_| o.borrow_mut().push("then 3"));
+    p.resolve(5);
+    assert_eq!(*order.borrow(), vec!["then 1", "then 2", "then 3", "then_move"]);
+}
+
+#[test]
+fn test_promise_then_move_first_order() {
+    let order = Rc::new(RefCell::new(vec![]));
+    let mut p = Promise::new();
+    let o = order.clone();
+    p.then_move(move |val: i32| o.borrow_mut().push(format!("then_move {}", val)));
+    let o = order.clone();
+    p.then(move |val| o.borrow_mut().push(format!("then {}", val)));
+    p.resolve(5);
+    assert_eq!(*order.borrow(), vec!["then 5".to_string(), "then_move 5".to_string()]);
+}
+
+#[test]
+fn test_promise_try_resolve_twice() {
+    let mut p = Promise::new();
+    assert_eq!(p.try_resolve(5), Ok(()));
+    assert_eq!(p.try_resolve(6), Err(6));
+    assert_eq!(*p.value().unwrap(), 5);
+    let mut p2 = Promise::new();
+    p2.then_move(|val: i32| val);
+    assert_eq!(p2.try_resolve(5), Ok(()));
+    assert_eq!(p2.try_resolve(6), Err(6));
+}
+
+#[test]
+#[should_panic(expected = "already been settled")]
+fn test_promise_resolve_twice() {
+    let mut p = Promise::new();
+    p.resolve(5);
+    p.resolve(6);
+}
+
+#[test]
+fn test_promise_downgrade() {
+    let mut p = Promise::new();
+    let weak = p.downgrade();
+    p.resolve(5);
+    assert_eq!(*weak.upgrade().unwrap().value().unwrap(), 5);
+    drop(p);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn test_promise_peek() {
+    let p = Promise::resolved(vec![1, 2, 3]);
+    let held = p.value().unwrap();
+    assert_eq!(p.peek(|values| values.len()), Some(3));
+    assert_eq!(held.len(), 3);
+    assert_eq!(Promise::<i32>::new().peek(|val| *val), None);
+}
+
+#[test]
+fn test_promise_cloned() {
+    let p = Promise::resolved("value".to_string());
+    assert_eq!(p.cloned(), Some("value".to_string()));
+    assert_eq!(p.cloned(), Some("value".to_string()));
+    assert!(p.is_resolved());
+    assert_eq!(Promise::<String>::new().cloned(), None);
+}
+
+#[test]
+fn test_async_runner_custom_executor() {
+    struct ImmediateExecutor;
+    impl Executor for ImmediateExecutor {
+        fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+            f();
+        }
+    }
+    let mut runner = AsyncRunner::with_executor(ImmediateExecutor);
+    let p = runner.exec_async(|| 5);
+    assert_eq!(runner.try_resolve_all(), 1);
+    assert_eq!(*p.value().unwrap(), 5);
+}
+
+#[test]
+fn test_async_runner_inline() {
+    let mut runner = AsyncRunner::new_inline();
+    let p = runner.exec_async(|| 5);
+    assert_eq!(*p.value().unwrap(), 5);
+    assert!(runner.is_idle());
+    let failed = runner.exec_async_result(|| Err::<i32, String>("failed".to_string()));
+    assert_eq!(*failed.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_async_runner_progress() {
+    let mut runner = AsyncRunner::new();
+    let (p, progress) = runner.exec_async_with_progress(|progress| {
+        for i in 1..4 {
+            progress.send(i * 10).unwrap();
+        }
+        "done"
+    });
+    runner.wait_all();
+    assert_eq!(progress.iter().collect::<Vec<i32>>(), vec![10, 20, 30]);
+    assert_eq!(*p.value().unwrap(), "done");
+}
+
+#[test]
+fn test_async_runner_retry() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async_retry(5, move || {
+        if calls2.fetch_add(1, Ordering::SeqCst) < 2 { Err("not yet") } else { Ok(42) }
+    });
+    assert_eq!(block_on(p, &mut runner), Ok(42));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    let p = runner.exec_async_retry(2, || Err::<i32, &str>("never"));
+    assert_eq!(block_on(p, &mut runner), Err("never"));
+}
+
+#[test]
+fn test_promise_stream() {
+    let mut runner = AsyncRunner::new();
+    let mut stream = PromiseStream::new();
+    stream.push(runner.exec_async(|| { thread::sleep(Duration::from_millis(150)); 3 }));
+    stream.push(runner.exec_async(|| 1));
+    stream.push(runner.exec_async(|| { thread::sleep(Duration::from_millis(50)); 2 }));
+    assert_eq!(stream.len(), 3);
+    let mut values = vec![];
+    loop {
+        match stream.poll_next() {
+            Poll::Ready(Some(value)) => values.push(value),
+            Poll::Ready(None) => break,
+            Poll::Pending => {
+                runner.try_resolve_all();
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+    assert_eq!(values, vec![1, 2, 3]);
+    assert!(stream.is_empty());
+}
+
+#[test]
+fn test_promise_stream_rejected() {
+    let mut stream = PromiseStream::new();
+    let mut failing = Promise::new();
+    stream.push(failing.clone());
+    stream.push(Promise::resolved(1));
+    assert_eq!(stream.len(), 2);
+    assert_eq!(stream.poll_next(), Poll::Ready(Some(1)));
+    assert_eq!(stream.poll_next(), Poll::Pending);
+    failing.reject(());
+    assert!(stream.is_empty());
+    assert_eq!(stream.poll_next(), Poll::Ready(None));
+}
+
+#[test]
+fn test_into_value_or() {
+    assert_eq!(Promise::resolved(1).into_value_or(2), 1);
+    assert_eq!(Promise::<i32>::new().into_value_or(2), 2);
+    assert_eq!(Promise::resolved(1).into_value_or_else(|| 2), 1);
+    assert_eq!(Promise::<i32>::new().into_value_or_else(|| 2), 2);
+}
+
+#[test]
+fn test_then_mut() {
+    let mut p = Promise::new();
+    let mut p2 = p.then_mut(|v: &mut Vec<i32>| v.push(3));
+    p.resolve(vec![1, 2]);
+    assert_eq!(*p2.value().unwrap(), vec![1, 2, 3]);
+    let p3 = p2.then_mut(|v| v.push(4));
+    assert_eq!(p3.into_value(), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn test_join_macro() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<String> = Promise::new();
+    let mut c = Promise::resolved(false);
+    let mut d: Promise<char> = Promise::new();
+    let mut e = Promise::resolved(2.5);
+    let j = join!(&mut a, &mut b, &mut c, &mut d, &mut e);
+    assert!(j.value().is_none());
+    d.resolve('x');
+    a.resolve(5);
+    assert!(j.value().is_none());
+    b.resolve("hello".to_string());
+    let expected = join5(&mut Promise::resolved(5), &mut Promise::resolved("hello".to_string()), &mut Promise::resolved(false), &mut Promise::resolved('x'), &mut Promise::resolved(2.5));
+    assert_eq!(*j.value().unwrap(), *expected.value().unwrap());
+    assert_eq!(join!(&mut Promise::resolved(1),).into_value(), (1,));
+    let mut ps: Vec<Promise<i32>> = (1..8).map(Promise::resolved).collect();
+    let (p1, rest) = ps.split_at_mut(1);
+    let seven = join!(&mut p1[0], &mut rest[0], &mut rest[1], &mut rest[2], &mut rest[3], &mut rest[4], &mut rest[5]);
+    assert_eq!(seven.into_value(), (1, 2, 3, 4, 5, 6, 7));
+}
+
+#[test]
+fn test_expect() {
+    assert_eq!(Promise::resolved(1).expect("should be resolved"), 1);
+}
+
+#[test]
+#[should_panic(expected = "config never loaded")]
+fn test_expect_unresolved() {
+    Promise::<i32>::new().expect("config never loaded");
+}
+
+#[test]
+fn test_promise_or_else() {
+    let mut p = Promise::<i32, String>::rejected("failed".to_string());
+    let p2 = p.or_else(|err| Promise::from_result(Ok(err.len() as i32)));
+    assert_eq!(*p2.value().unwrap(), 6);
+
+    let mut p = Promise::<i32, String>::rejected("failed".to_string());
+    let p2 = p.or_else(|err| Promise::rejected(err + " twice"));
+    assert_eq!(*p2.error().unwrap(), "failed twice");
+}
+
+#[test]
+fn test_promise_or_else_pending() {
+    let mut p = Promise::new();
+    let mut recovery = Promise::new();
+    let r = recovery.clone();
+    let p2 = p.then_move_promise(|_: i32| Promise::rejected(())).or_else(move |_| r);
+    p.resolve(5);
+    assert!(p2.value().is_none());
+    recovery.resolve(10);
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_promise_zip() {
+    let mut a: Promise<i32> = Promise::new();
+    let mut b: Promise<String> = Promise::new();
+    let j = a.zip(&mut b).then(|(i, s)| format!("{} _ {}", i, s));
+    assert!(j.value().is_none());
+    a.resolve(5);
+    assert!(j.value().is_none());
+    b.resolve("hello".to_string());
+    assert_eq!(*j.value().unwrap(), "5 _ hello".to_string());
+}
+
+#[test]
+fn test_async_runner_on_all_settled() {
+    let fired = Rc::new(Cell::new(0));
+    let fired2 = fired.clone();
+    let mut runner = AsyncRunner::new();
+    let a = runner.exec_async(|| 1);
+    let b = runner.exec_async(|| { thread::sleep(Duration::from_millis(20)); 2 });
+    runner.on_all_settled(move || fired2.set(fired2.get() + 1));
+    assert_eq!(block_on(a, &mut runner) + block_on(b, &mut runner), 3);
+    assert_eq!(fired.get(), 1);
+    runner.try_resolve_all();
+    assert_eq!(fired.get(), 1);
+}
+
+#[test]
+fn test_async_runner_drain_completed() {
+    let mut runner = AsyncRunner::new();
+    let (tx, rx) = mpsc::channel::<()>();
+    let _a = runner.exec_async(|| 1);
+    let _b = runner.exec_async(move || rx.recv().unwrap());
+    let _c = runner.exec_async(|| 3);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(runner.try_resolve_all(), 2);
+    assert_eq!(runner.drain_completed(), vec![0, 2]);
+    assert!(runner.drain_completed().is_empty());
+    tx.send(()).unwrap();
+    while runner.try_resolve_all() == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(runner.drain_completed(), vec![1]);
+}
+
+#[test]
+fn test_promise_deferred() {
+    let (resolver, mut p) = Promise::deferred();
+    let p2 = p.then(|v: &i32| v * 2);
+    assert!(p.value().is_none());
+    resolver.resolve(5);
+    assert_eq!(*p.value().unwrap(), 5);
+    assert_eq!(*p2.value().unwrap(), 10);
+}
+
+#[test]
+fn test_async_runner_bounded_progress() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let sent = Arc::new(AtomicUsize::new(0));
+    let sent2 = sent.clone();
+    let mut runner = AsyncRunner::new();
+    let (p, progress) = runner.exec_async_with_bounded_progress(1, move |tx| {
+        for i in 0..3 {
+            tx.send(i).unwrap();
+            sent2.fetch_add(1, Ordering::SeqCst);
+        }
+        "done"
+    });
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(sent.load(Ordering::SeqCst), 1);
+    assert!(p.value().is_none());
+    assert_eq!(progress.iter().collect::<Vec<i32>>(), vec![0, 1, 2]);
+    assert_eq!(block_on(p, &mut runner), "done");
+}
+
+#[test]
+fn test_promise_then_move_result() {
+    let mut p = Promise::resolved("12".to_string());
+    let p2 = p.then_move_result(|val| val.parse::<i32>().map_err(|_| ()));
+    assert_eq!(*p2.value().unwrap(), 12);
+
+    let mut p = Promise::new();
+    let p2 = p.then_move_result(|val: String| val.parse::<i32>().map_err(|_| ()));
+    p.resolve("not a number".to_string());
+    assert!(p2.value().is_none());
+    assert!(p2.error().is_some());
+}
+
+#[test]
+fn test_async_runner_map_async() {
+    let mut runner = AsyncRunner::new();
+    let promises = runner.map_async(vec![1, 2, 3, 4], |x| x * 2);
+    assert_eq!(promises.len(), 4);
+    let all = join_all(promises);
+    assert_eq!(block_on(all, &mut runner), vec![2, 4, 6, 8]);
+}
+
+#[test]
+fn test_async_runner_poll() {
+    let mut runner = AsyncRunner::new();
+    let (tx, rx) = mpsc::channel::<()>();
+    let _a = runner.exec_async(|| 1);
+    let _b = runner.exec_async(|| 2);
+    let _c = runner.exec_async(move || rx.recv().unwrap());
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(runner.poll(), PollOutcome { resolved: 2, pending: 1, idle: false });
+    tx.send(()).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(runner.poll(), PollOutcome { resolved: 1, pending: 0, idle: true });
+}
 
 #[test]
-fn test_promise_then_move_promise() {
+fn test_promise_poll() {
     let mut p = Promise::new();
-    let p2 = p.then_move_promise(|val| Promise::resolved(val * 2));
+    assert!(p.poll().is_pending());
     p.resolve(5);
-    assert_eq!(*p2.value().unwrap(), 10);
+    match p.poll() {
+        Poll::Ready(value) => assert_eq!(*value, 5),
+        Poll::Pending => panic!("expected the promise to be ready")
+    }
+    assert_eq!(p.into_value(), 5);
 }
 
 #[test]
-fn test_promise_then() {
-    let mut p = Promise::new();
-    let p2 = p.then(|val| val * 2);
-    p.resolve(5);
-    assert_eq!(*p2.value().unwrap(), 10);
+fn test_promise_from_receiver() {
+    let (tx, rx) = mpsc::channel();
+    let (p, source) = Promise::from_receiver(rx);
+    let mut runner = AsyncRunner::new();
+    runner.track(Box::new(source));
+    assert_eq!(runner.pending(), 1);
+    runner.try_resolve_all();
+    assert!(p.value().is_none());
+    thread::spawn(move || tx.send("hello").unwrap());
+    assert_eq!(block_on(p, &mut runner), "hello");
+    assert!(runner.is_idle());
 }
 
 #[test]
-fn test_then_after_resolved() {
+fn test_async_runner_track_custom() {
+    struct Countdown {
+        remaining: Cell<u32>,
+        promise: Promise<&'static str>
+    }
+    impl Resolveable for Countdown {
+        fn try_resolve(&self) -> Resolution {
+            self.remaining.set(self.remaining.get() - 1);
+            if self.remaining.get() > 0 {
+                return Resolution::Pending;
+            }
+            self.promise.clone().resolve("liftoff");
+            Resolution::Settled
+        }
+        fn wait(&self) {
+            while self.try_resolve() == Resolution::Pending {}
+        }
+    }
+    let p = Promise::new();
+    let mut runner = AsyncRunner::new();
+    runner.track(Box::new(Countdown { remaining: Cell::new(3), promise: p.clone() }));
+    assert_eq!(runner.try_resolve_all(), 0);
+    assert_eq!(runner.try_resolve_all(), 0);
+    assert!(p.value().is_none());
+    assert_eq!(runner.try_resolve_all(), 1);
+    assert_eq!(*p.value().unwrap(), "liftoff");
+    assert!(runner.is_idle());
+}
+
+#[test]
+fn test_running_new() {
+    let (tx, rx) = mpsc::channel();
+    let (p, running) = Running::<i32, String>::new(rx);
+    let mut runner = AsyncRunner::new();
+    runner.track(Box::new(running));
+    tx.send(Err("failed".to_string())).unwrap();
+    runner.try_resolve_all();
+    assert_eq!(*p.error().unwrap(), "failed");
+}
+
+#[test]
+fn test_promise_chain_short_circuits_on_rejection() {
+    let calls = Rc::new(Cell::new(0));
+    let (c1, c2, c3) = (calls.clone(), calls.clone(), calls.clone());
+    let mut p = Promise::<i32, String>::pending();
+    let p2 = p
+        .then_move_result(move |val| { c1.set(c1.get() + 1); if val > 0 { Ok(val) } else { Err(format!("bad value {}", val)) } })
+        .then_move(move |val| { c2.set(c2.get() + 1); val * 2 })
+        .then(move |val| { c3.set(c3.get() + 1); val + 1 });
+    p.resolve(-1);
+    assert_eq!(calls.get(), 1);
+    assert!(p2.value().is_none());
+    assert_eq!(*p2.error().unwrap(), "bad value -1");
+}
+
+#[test]
+fn test_promise_chain_short_circuits_on_rejected_source() {
+    let calls = Rc::new(Cell::new(0));
+    let (c1, c2, c3) = (calls.clone(), calls.clone(), calls.clone());
+    let mut p = Promise::<i32, &str>::rejected("upstream failed");
+    let p2 = p
+        .then_move(move |val| { c1.set(c1.get() + 1); val + 1 })
+        .then(move |val| { c2.set(c2.get() + 1); val * 2 })
+        .then_move_promise(move |val| { c3.set(c3.get() + 1); Promise::from_result(Ok(val)) });
+    assert_eq!(calls.get(), 0);
+    assert_eq!(*p2.error().unwrap(), "upstream failed");
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn test_promise_into_futures() {
+    use futures::executor::LocalPool;
+    use futures::future::FusedFuture;
+    use futures::task::LocalSpawnExt;
     let mut p = Promise::new();
-    p.resolve(7);
-    let p2 = p.then(|x| x * 2);
-    assert_eq!(*p2.value().unwrap(), 14);
+    let mut fut = p.clone().into_futures();
+    let mut pool = LocalPool::new();
+    pool.spawner().spawn_local(futures::future::lazy(move |_| p.resolve(5))).unwrap();
+    assert!(!fut.is_terminated());
+    assert_eq!(pool.run_until(&mut fut), Ok(5));
+    assert!(fut.is_terminated());
 }
 
 #[test]
-fn test_promise_then_then_move() {
+fn test_assert_resolved_eq() {
+    assert_resolved_eq(&Promise::resolved(5), 5);
     let mut p = Promise::new();
-    let p2 = p.then(|val| val * 2);
-    let p3 = p.then_move(|val| val * 3);
-    p.resolve(5);
-    assert!(p.value().is_none());
-    assert_eq!(*p2.value().unwrap(), 10);
-    assert_eq!(*p3.value().unwrap(), 15);
+    let p2 = p.then(|v: &i32| v * 2);
+    p.resolve(2);
+    assert_resolved_eq(&p2, 4);
 }
 
 #[test]
-#[should_panic]
-fn test_promise_then_move_then_move() {
-    let mut p = Promise::<i32>::new();
-    p.then_move(|val| val * 2);
-    p.then_move(|val| val * 3);
+#[should_panic(expected = "Expected promise to resolve to 5, but it resolved to 6.")]
+fn test_assert_resolved_eq_unequal() {
+    assert_resolved_eq(&Promise::resolved(6), 5);
 }
 
 #[test]
-#[should_panic]
-fn test_promise_resolved_then_move_then_move() {
-    let mut p = Promise::resolved(5);
-    p.then_move(|val| val * 2);
-    p.then_move(|val| val * 3);
+#[should_panic(expected = "Expected promise to resolve to 5, but it is Promise(Unresolved).")]
+fn test_assert_resolved_eq_pending() {
+    assert_resolved_eq(&Promise::new(), 5);
 }
 
 #[test]
-#[should_panic]
-fn test_promise_resolved_then_move_then() {
-    let mut p = Promise::resolved(5);
-    p.then_move(|val| val * 2);
-    p.then(|val| val * 3);
+#[should_panic(expected = "Expected promise to resolve to 5, but it was rejected with \"failed\".")]
+fn test_assert_resolved_eq_rejected() {
+    assert_resolved_eq(&Promise::<i32, &str>::rejected("failed"), 5);
 }
 
 #[test]
-fn test_promise_join() {
-    let mut a: Promise<i32> = Promise::new();
+fn test_promise_then_move_cloned() {
+    let mut p = Promise::new();
+    let a = p.then_move_cloned(|mut v: Vec<i32>| { v.push(3); v });
+    let b = p.then_move_cloned(|mut v: Vec<i32>| { v.push(4); v });
+    p.resolve(vec![1, 2]);
+    assert_eq!(a.into_value(), vec![1, 2, 3]);
+    assert_eq!(b.into_value(), vec![1, 2, 4]);
+    assert_eq!(*p.value().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn test_join_first_some() {
+    let mut a: Promise<Option<i32>> = Promise::new();
+    let mut b: Promise<Option<i32>> = Promise::new();
+    let mut c: Promise<Option<i32>> = Promise::new();
+    let mut d: Promise<Option<i32>> = Promise::new();
+    let p = join_first_some(vec![&mut a, &mut b, &mut c, &mut d]);
+    c.resolve(Some(3));
+    assert!(p.value().is_none());
+    a.resolve(None);
+    assert!(p.value().is_none());
+    b.resolve(None);
+    assert_eq!(*p.value().unwrap(), Some(3));
+    d.resolve(Some(4));
+    assert_eq!(*p.value().unwrap(), Some(3));
+}
+
+#[test]
+fn test_join_first_some_all_none() {
+    let mut a: Promise<Option<i32>> = Promise::new();
+    let mut b = Promise::resolved(None);
+    let p = join_first_some(vec![&mut a, &mut b]);
+    assert!(p.value().is_none());
+    a.resolve(None);
+    assert_eq!(*p.value().unwrap(), None);
+    assert_eq!(*join_first_some::<i32, ()>(vec![]).value().unwrap(), None);
+}
+
+#[test]
+fn test_promise_join_one_pre_resolved() {
+    let mut a = Promise::resolved(5);
     let mut b: Promise<String> = Promise::new();
-    let j = (&mut a, &mut b).join().then(|&(ref i, ref s)| format!("{} _ {}", i, s));
-    assert!(j.value().is_none());
-    a.resolve(5);
+    let j = (&mut a, &mut b).join();
     assert!(j.value().is_none());
     b.resolve("hello".to_string());
-    assert_eq!(*j.value().unwrap(), "5 _ hello".to_string());
-}
+    assert_eq!(*j.value().unwrap(), (5, "hello".to_string()));
 
-#[test]
-fn test_promise_join3() {
     let mut a: Promise<i32> = Promise::new();
-    let mut b: Promise<String> = Promise::new();
-    let mut c: Promise<String> = Promise::new();
-    let j = (&mut a, &mut b, &mut c).join().then(|&(ref i, ref s, ref s2)| format!("{} _ {} {}", i, s, s2));
+    let mut b = Promise::resolved("hello".to_string());
+    let j = (&mut a, &mut b).join();
     assert!(j.value().is_none());
     a.resolve(5);
-    assert!(j.value().is_none());
-    b.resolve("hello".to_string());
-    c.resolve("world".to_string());
-    assert_eq!(*j.value().unwrap(), "5 _ hello world".to_string());
+    assert_eq!(*j.value().unwrap(), (5, "hello".to_string()));
 }
 
 #[test]
-fn test_promise_array_join() {
-    let mut a: Promise<i32> = Promise::new();
+fn test_promise_join_all_pre_resolved() {
+    let mut a = Promise::resolved(5);
+    let mut b = Promise::resolved("hello".to_string());
+    let j = (&mut a, &mut b).join();
+    assert_eq!(*j.value().unwrap(), (5, "hello".to_string()));
+}
+
+#[test]
+fn test_promise_vec_join_pre_resolved() {
+    let mut a = Promise::resolved(1);
     let mut b: Promise<i32> = Promise::new();
-    let j: Promise<Vec<i32>> = vec![&mut a, &mut b].join();
-    assert!(j.value().is_none());
-    a.resolve(5);
+    let mut c = Promise::resolved(3);
+    let j = vec![&mut a, &mut b, &mut c].join();
     assert!(j.value().is_none());
-    b.resolve(7);
-    assert_eq!(*j.value().unwrap(), vec![5, 7]);
+    b.resolve(2);
+    assert_eq!(*j.value().unwrap(), vec![1, 2, 3]);
+
+    let mut a = Promise::resolved(1);
+    let mut b = Promise::resolved(2);
+    let j = vec![&mut a, &mut b].join();
+    assert_eq!(*j.value().unwrap(), vec![1, 2]);
 }
 
 #[test]
-fn test_promise_async() {
+fn test_promise_eq() {
+    assert!(Promise::resolved(5) == Promise::resolved(5));
+    assert!(Promise::resolved(5) != Promise::resolved(6));
+    assert!(Promise::resolved(5) != Promise::new());
+    let p = Promise::<i32>::new();
+    assert!(p != p.clone());
+    let mut moved = Promise::resolved(5);
+    moved.then_move(|v| v);
+    assert!(moved != Promise::resolved(5));
+}
+
+#[test]
+fn test_async_runner_wait_all_timeout() {
     let mut runner = AsyncRunner::new();
-    let p = runner.exec_async(|| {
-        thread::sleep_ms(10);
-        "Hello world from thread".to_string()
+    let (tx, rx) = mpsc::channel::<i32>();
+    let quick = runner.exec_async(|| 1);
+    let stuck = runner.exec_async(move || rx.recv().unwrap_or(0));
+    assert!(!runner.wait_all_timeout(Duration::from_millis(50)));
+    assert_eq!(*quick.value().unwrap(), 1);
+    assert!(stuck.value().is_none());
+    assert_eq!(runner.pending(), 1);
+    tx.send(2).unwrap();
+    assert!(runner.wait_all_timeout(Duration::from_secs(5)));
+    assert_eq!(*stuck.value().unwrap(), 2);
+}
+
+#[test]
+fn test_promise_then_spawn() {
+    let mut runner = AsyncRunner::new();
+    let mut p = Promise::new();
+    let main_thread = thread::current().id();
+    let p2 = p.then_spawn(&mut runner, move |n: u64| {
+        assert!(thread::current().id() != main_thread);
+        (1..=n).sum::<u64>()
     });
+    thread::sleep(Duration::from_millis(20));
     runner.try_resolve_all();
-    assert!(p.value().is_none());
-    thread::sleep_ms(20);
+    assert!(p2.value().is_none());
+    p.resolve(1000);
+    assert_eq!(block_on(p2, &mut runner), 500500);
+}
+
+#[test]
+fn test_promise_then_spawn_single_worker() {
+    let mut runner = AsyncRunner::new_pooled(1);
+    let (resolver, mut p) = Promise::deferred();
+    let p2 = p.then_spawn(&mut runner, |n: i32| n * 2);
+    let mut source = runner.exec_async(|| 21);
+    source.then_move(move |n| resolver.resolve(n));
+    assert_eq!(block_on(p2, &mut runner), 42);
+}
+
+#[test]
+fn test_promise_then_spawn_rejected() {
+    let mut runner = AsyncRunner::new();
+    let mut p = Promise::new();
+    let p2 = p.then_move_result(|_: i32| Err::<i32, ()>(())).then_spawn(&mut runner, |n| n + 1);
+    p.resolve(1);
+    runner.wait_all();
+    assert!(p2.error().is_some());
+}
+
+#[test]
+fn test_promise_callback_count() {
+    let mut p = Promise::<i32>::new();
+    assert_eq!(p.callback_count(), 0);
+    p.then(|v| v + 1);
+    p.on_resolve(|_| {});
+    assert_eq!(p.callback_count(), 2);
+    p.then_move(|v| v * 2);
+    p.then(|v| v + 2);
+    assert_eq!(p.callback_count(), 4);
+    p.resolve(1);
+    assert_eq!(p.callback_count(), 0);
+}
+
+#[test]
+fn test_promise_reject() {
+    let handled = Rc::new(Cell::new(false));
+    let handled2 = handled.clone();
+    let mut p = Promise::<i32>::new();
+    let mut p2 = p.then(|v| v * 2);
+    let p3 = p2.map_err(|_| "failed").catch(move |err| { handled2.set(true); err.len() as i32 });
+    p.reject(());
+    assert!(p.error().is_some());
+    assert!(p2.value().is_none());
+    assert!(handled.get());
+    assert_eq!(*p3.value().unwrap(), 6);
+    assert_eq!(p.try_reject(()), Err(()));
+}
+
+#[test]
+#[should_panic(expected = "Trying to reject a promise that has already been settled.")]
+fn test_promise_reject_resolved() {
+    Promise::resolved(1).reject(());
+}
+
+#[test]
+fn test_promise_from_result() {
+    let p = Promise::from_result("5".parse::<i32>());
+    assert!(p.is_resolved());
+    assert_eq!(*p.value().unwrap(), 5);
+
+    let mut p = Promise::from_result("five".parse::<i32>());
+    assert!(p.is_rejected());
+    assert!(p.error().is_some());
+    assert_eq!(*p.catch(|_| -1).value().unwrap(), -1);
+}
+
+#[test]
+fn test_promise_then_after() {
+    let log = Rc::new(RefCell::new(vec![]));
+    let log2 = log.clone();
+    let mut sink = Promise::new();
+    let sink2 = sink.clone();
+    let mut p = Promise::new();
+    let p2 = p.then_after(move |v: &String| {
+        log2.borrow_mut().push(v.clone());
+        sink2
+    });
+    p.resolve("hello".to_string());
+    assert_eq!(*log.borrow(), vec!["hello".to_string()]);
+    assert!(p2.value().is_none());
+    sink.resolve(());
+    assert_eq!(*p2.value().unwrap(), "hello");
+}
+
+#[test]
+fn test_async_runner_shutdown() {
+    let mut runner = AsyncRunner::new();
+    let before = runner.exec_async(|| { thread::sleep(Duration::from_millis(20)); 1 });
+    runner.shutdown();
+    assert!(runner.is_shut_down());
+    let after = runner.exec_async(|| 2);
+    assert!(after.error().is_some());
+    let after_result = runner.exec_async_result(|| Ok::<i32, String>(3));
+    assert_eq!(*after_result.error().unwrap(), String::from(ShutDown));
+    let mut upstream = Promise::resolved(3);
+    assert!(upstream.then_spawn(&mut runner, |v| v * 2).error().is_some());
+    assert_eq!(runner.pending(), 1);
+    assert_eq!(block_on(before, &mut runner), 1);
+}
+
+#[test]
+fn test_async_runner_shutdown_now() {
+    let mut runner = AsyncRunner::new();
+    runner.set_max_concurrent(1);
+    let (tx, rx) = mpsc::channel::<()>();
+    let first = runner.exec_async(move || rx.recv().unwrap());
+    let queued = runner.exec_async(|| 2);
+    runner.shutdown_now();
+    tx.send(()).unwrap();
+    runner.wait_all();
+    assert!(first.value().is_some());
+    assert!(queued.value().is_none() && queued.error().is_none());
+}
+
+#[test]
+fn test_promise_map_ref_view() {
+    struct User {
+        name: String,
+        age: u32
+    }
+    let mut p = Promise::new();
+    assert!(p.map_ref_view(|user: &User| &user.name).is_none());
+    p.resolve(User { name: "Ada".to_string(), age: 36 });
+    assert_eq!(*p.map_ref_view(|user| &user.name).unwrap(), "Ada");
+    assert_eq!(&*p.map_ref_view(|user| user.name.as_str()).unwrap(), "Ada");
+    assert_eq!(*p.map_ref_view(|user| &user.age).unwrap(), 36);
+}
+
+#[test]
+fn test_async_runner_try_resolve_some() {
+    let mut runner = AsyncRunner::new();
+    let promises = runner.map_async(vec![1, 2, 3, 4, 5], |x| x);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(runner.try_resolve_some(2), 2);
+    assert_eq!(runner.drain_completed(), vec![0, 1]);
+    assert_eq!(runner.pending(), 3);
+    assert!(promises[2].value().is_none());
+    assert_eq!(runner.try_resolve_some(2), 2);
+    assert_eq!(runner.try_resolve_some(2), 1);
+    assert!(runner.is_idle());
+    assert_eq!(*promises[4].value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_into_value_iter() {
+    let mut a = Promise::new();
+    let mut b = Promise::new();
+    let j = vec![&mut a, &mut b].join();
+    a.resolve(1);
+    b.resolve(2);
+    assert_eq!(j.into_value_iter().map(|x| x * 10).collect::<Vec<i32>>(), vec![10, 20]);
+}
+
+#[test]
+#[should_panic]
+fn test_promise_into_value_iter_unresolved() {
+    let _ = Promise::<Vec<i32>>::new().into_value_iter();
+}
+
+#[test]
+fn test_promise_reentrant_resolve() {
+    let mut p = Promise::new();
+    let mut sibling = p.clone();
+    let reader = p.clone();
+    let mut q = Promise::new();
+    let q2 = q.clone();
+    let seen = Rc::new(Cell::new(0));
+    let seen2 = seen.clone();
+    q.on_resolve(move |_| seen2.set(*reader.value().unwrap()));
+    p.on_resolve(move |v: &i32| {
+        assert_eq!(sibling.try_resolve(v + 1), Err(v + 1));
+        assert_eq!(*sibling.value().unwrap(), 5);
+        let mut chained = sibling.then(|v| v * 2);
+        assert_eq!(*chained.value().unwrap(), 10);
+        chained.then_move(|_| ());
+        let mut q2 = q2.clone();
+        q2.resolve(());
+    });
+    let p2 = p.then_move(|v| v + 1);
+    p.resolve(5);
+    assert_eq!(seen.get(), 5);
+    assert_eq!(*p2.value().unwrap(), 6);
+}
+
+#[test]
+fn test_promise_infallible() {
+    let p = Promise::resolved(5).infallible();
+    assert_eq!(*p.value().unwrap(), Ok(5));
+
+    let mut p = Promise::new();
+    let p2 = p.clone().infallible();
+    p.resolve("hello");
+    match p2.into_value() {
+        Ok(value) => assert_eq!(value, "hello"),
+        Err(never) => match never {}
+    }
+}
+
+#[test]
+fn test_async_runner_cancel_task() {
+    let mut runner = AsyncRunner::new();
+    let (p, handle) = runner.exec_async_cancellable(|| { thread::sleep(Duration::from_millis(50)); 1 });
+    let (kept, _) = runner.exec_async_cancellable(|| 2);
+    handle.cancel();
+    assert!(handle.is_cancelled());
+    runner.wait_all();
     runner.try_resolve_all();
-    assert_eq!(*p.value().unwrap(), "Hello world from thread");
+    assert!(p.value().is_none() && p.error().is_none());
+    assert_eq!(*kept.value().unwrap(), 2);
+    assert!(runner.is_idle());
+
+    let (_p, handle) = runner.exec_async_cancellable(|| { thread::sleep(Duration::from_millis(50)); 3 });
+    handle.cancel();
+    assert_eq!(runner.try_resolve_all(), 0);
+    assert!(runner.drain_completed().is_empty());
+    assert!(runner.is_idle());
+}
+
+#[test]
+fn test_async_runner_cancel_queued_task() {
+    use std::sync::atomic::AtomicUsize;
+    let started = Arc::new(AtomicUsize::new(0));
+    let started2 = started.clone();
+    let mut runner = AsyncRunner::new();
+    runner.set_max_concurrent(1);
+    let (tx, rx) = mpsc::channel::<()>();
+    let first = runner.exec_async(move || rx.recv().unwrap());
+    let (queued, handle) = runner.exec_async_cancellable(move || started2.fetch_add(1, Ordering::SeqCst));
+    handle.cancel();
+    tx.send(()).unwrap();
+    runner.wait_all();
+    assert!(first.value().is_some());
+    assert!(queued.value().is_none());
+    assert_eq!(started.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_promise_then_move_eager() {
+    let mut p = Promise::resolved(5);
+    let (mut p2, eager) = p.then_move_eager(|v| v + 1);
+    assert!(eager);
+    let (p3, eager) = p2.then_move_eager(|v| v * 2);
+    assert!(eager);
+    assert_eq!(p3.into_value(), 12);
+
+    let mut p = Promise::lazy(|| 1);
+    assert!(p.then_move_eager(|v| v).1);
+
+    let mut p = Promise::new();
+    let (p2, eager) = p.then_move_eager(|v: i32| v + 1);
+    assert!(!eager);
+    p.resolve(1);
+    assert_eq!(p2.into_value(), 2);
+}
+
+#[test]
+fn test_all_with_index() {
+    let mut a = Promise::new();
+    let mut b = Promise::new();
+    let mut c = Promise::new();
+    let p = all_with_index(vec![&mut a, &mut b, &mut c]);
+    c.resolve("c");
+    a.resolve("a");
+    assert!(p.value().is_none());
+    b.resolve("b");
+    assert_eq!(*p.value().unwrap(), vec![(0, "a"), (1, "b"), (2, "c")]);
+}
+
+#[test]
+fn test_async_runner_throttled() {
+    let interval = Duration::from_millis(30);
+    let starts = Arc::new(Mutex::new(vec![]));
+    // The inline executor runs each task as it is launched, so start times are exact.
+    let mut runner = AsyncRunner::new_inline();
+    let promises: Vec<Promise<()>> = (0..3).map(|_| {
+        let starts = starts.clone();
+        runner.exec_async_throttled(interval, move || starts.lock().unwrap().push(Instant::now()))
+    }).collect();
+    assert_eq!(starts.lock().unwrap().len(), 1);
+    assert_eq!(runner.pending(), 2);
+    let all = join_all(promises);
+    block_on(all, &mut runner);
+    let starts = starts.lock().unwrap();
+    assert_eq!(starts.len(), 3);
+    assert!(starts[1] - starts[0] >= interval);
+    assert!(starts[2] - starts[1] >= interval);
+}
+
+#[test]
+fn test_async_runner_throttled_max_concurrent() {
+    struct RecordingExecutor(Arc<Mutex<Vec<Instant>>>);
+    impl Executor for RecordingExecutor {
+        fn spawn(&self, f: Box<dyn FnOnce() + Send>) {
+            self.0.lock().unwrap().push(Instant::now());
+            thread::spawn(f);
+        }
+    }
+    let interval = Duration::from_millis(20);
+    let starts = Arc::new(Mutex::new(vec![]));
+    let mut runner = AsyncRunner::with_executor(RecordingExecutor(starts.clone()));
+    runner.set_max_concurrent(1);
+    // Holds the only slot for longer than the throttled tasks would take to become due.
+    let blocker = runner.exec_async(|| thread::sleep(Duration::from_millis(60)));
+    let promises: Vec<Promise<()>> = (0..3).map(|_| runner.exec_async_throttled(interval, || ())).collect();
+    runner.wait_all();
+    assert!(promises.iter().all(|p| p.value().is_some()) && blocker.value().is_some());
+    let starts = starts.lock().unwrap();
+    assert_eq!(starts.len(), 4);
+    assert!(starts[2] - starts[1] >= interval);
+    assert!(starts[3] - starts[2] >= interval);
+}
+
+#[test]
+fn test_async_runner_throttled_before_queued() {
+    let order = Arc::new(Mutex::new(vec![]));
+    let mut runner = AsyncRunner::builder().max_concurrent(1).build();
+    let (tx, rx) = mpsc::channel::<()>();
+    let blocker = runner.exec_async(move || rx.recv().unwrap());
+    let queued_order = order.clone();
+    let queued = runner.exec_async(move || queued_order.lock().unwrap().push("queued"));
+    let throttled_order = order.clone();
+    let throttled = runner.exec_async_throttled(Duration::from_millis(0), move || throttled_order.lock().unwrap().push("throttled"));
+    tx.send(()).unwrap();
+    runner.wait_all();
+    assert!(blocker.value().is_some() && queued.value().is_some() && throttled.value().is_some());
+    assert_eq!(*order.lock().unwrap(), vec!["throttled", "queued"]);
+}
+
+#[test]
+fn test_promise_timed() {
+    let mut runner = AsyncRunner::new();
+    let p = runner.exec_async(|| { thread::sleep(Duration::from_millis(20)); 5 }).timed();
+    let (value, elapsed) = block_on(p, &mut runner);
+    assert_eq!(value, 5);
+    assert!(elapsed > Duration::from_millis(0));
+}
+
+#[test]
+fn test_promise_recover_default() {
+    let mut p = Promise::<Vec<i32>, String>::rejected("failed".to_string());
+    assert_eq!(*p.recover_default().value().unwrap(), Vec::<i32>::new());
+
+    let mut p = Promise::new();
+    let p2 = p.recover_default();
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 5);
+}
+
+#[test]
+fn test_send_promise_send_resolver() {
+    let mut p = SendPromise::new();
+    let resolver = p.send_resolver();
+    let p2 = p.then_move(|value: i32| value * 2);
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        resolver.resolve(21);
+    });
+    assert_eq!(p2.wait(), 42);
+
+    let p = SendPromise::new();
+    let resolver = p.send_resolver();
+    thread::spawn(move || resolver.resolve("done")).join().unwrap();
+    assert_eq!(p.value(), Some("done"));
+}
+
+#[test]
+fn test_async_runner_builder() {
+    let mut runner = AsyncRunner::builder().pool(2).max_concurrent(1).build();
+    let a = runner.exec_async(|| 1);
+    let b = runner.exec_async(|| 2);
+    assert_eq!(block_on(a, &mut runner) + block_on(b, &mut runner), 3);
+
+    let mut runner = AsyncRunner::builder().inline().build();
+    let p = runner.exec_async(|| 5);
+    assert_eq!(*p.value().unwrap(), 5);
+}
+
+#[test]
+fn test_promise_then_ctx() {
+    let mut p = Promise::new();
+    let p2 = p.clone()
+        .with_context("req-42".to_string())
+        .then_ctx(|id, v: i32| format!("{}: {}", id, v))
+        .then_ctx(|id, s| (id.len(), s.len()));
+    p.resolve(7);
+    let (id, (id_len, s_len)) = p2.into_value();
+    assert_eq!(id, "req-42");
+    assert_eq!((id_len, s_len), (6, 9));
+}
+
+#[test]
+fn test_async_runner_drop_skips_adopted_sources() {
+    let (tx, rx) = mpsc::channel::<i32>();
+    let (p, source) = Promise::from_receiver(rx);
+    let mut runner = AsyncRunner::new();
+    runner.track(Box::new(source));
+    let task = runner.exec_async(|| { thread::sleep(Duration::from_millis(20)); 1 });
+    drop(runner);
+    assert!(task.value().is_some());
+    assert!(p.value().is_none());
+    drop(tx);
+}
+
+#[test]
+fn test_promise_settle_after_try_resolve() {
+    let mut p = Promise::new();
+    let mut p2 = p.then(|v: &i32| v * 2);
+    assert!(p2.try_resolve(1).is_ok());
+    p.resolve(5);
+    assert_eq!(*p2.value().unwrap(), 1);
+}
+
+#[test]
+#[should_panic(expected = "already been settled")]
+fn test_resolver_after_manual_resolve() {
+    let (resolver, mut p) = Promise::deferred();
+    p.resolve(1);
+    resolver.resolve(2);
+}
+
+#[test]
+#[should_panic(expected = "while its callbacks are borrowing it")]
+fn test_promise_then_move_sibling_in_callback() {
+    let mut p = Promise::new();
+    let mut sibling = p.clone();
+    p.on_resolve(move |_: &i32| {
+        sibling.then_move(|v| v + 1);
+    });
+    p.resolve(5);
+}
+
+#[test]
+fn test_send_promise_double_resolve_keeps_value() {
+    let mut p = SendPromise::new();
+    p.resolve(1);
+    let mut p2 = p.clone();
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| p2.resolve(2))).is_err());
+    assert_eq!(p.value(), Some(1));
 }